@@ -0,0 +1,247 @@
+//! Action logs written by `--output`, as JSON or a compact binary format.
+//!
+//! The binary format is the magic bytes `KLOG` and a version byte, followed by
+//! one record per action until the end of the file:
+//!
+//! - the timestamp as a zigzag varint delta from the previous action's (from 0
+//!   for the first), so a ledger in timestamp order costs a byte or two each;
+//! - one byte holding the action kind in the low two bits and the target
+//!   location in the next two;
+//! - the target unit as a varint;
+//! - the order id as a varint length and its UTF-8 bytes.
+//!
+//! Like the JSON, it holds only what is submitted to the server.
+
+use crate::model::{Action, ActionKind, Location};
+
+use anyhow::{Context, Result, anyhow, bail};
+
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"KLOG";
+const VERSION: u8 = 1;
+
+const KINDS: [ActionKind; 4] = [
+    ActionKind::Place,
+    ActionKind::Move,
+    ActionKind::Pickup,
+    ActionKind::Discard,
+];
+
+/// How `--output` writes the collected actions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// A pretty-printed JSON array, as submitted to the server
+    #[default]
+    Json,
+    /// The compact binary format described in this module
+    Bin,
+}
+
+pub fn write_actions(path: &Path, actions: &[Action], format: LogFormat) -> Result<()> {
+    let bytes = match format {
+        LogFormat::Json => serde_json::to_vec_pretty(actions)?,
+        LogFormat::Bin => encode(actions),
+    };
+    fs::write(path, bytes).with_context(|| format!("failed to write actions to {}", path.display()))
+}
+
+/// Reads an action log in either format, telling them apart by the magic bytes.
+pub fn read_actions(path: &Path) -> Result<Vec<Action>> {
+    let bytes = fs::read(path)
+        .with_context(|| format!("failed to read actions from {}", path.display()))?;
+    let actions = if bytes.starts_with(MAGIC) {
+        decode(&bytes)
+    } else {
+        serde_json::from_slice(&bytes).map_err(Into::into)
+    };
+    actions.with_context(|| format!("malformed action log {}", path.display()))
+}
+
+pub fn encode(actions: &[Action]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(MAGIC.len() + 1 + actions.len() * 12);
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+
+    let mut previous = 0u64;
+    for action in actions {
+        let delta = action.timestamp.wrapping_sub(previous) as i64;
+        previous = action.timestamp;
+        write_varint(&mut out, ((delta << 1) ^ (delta >> 63)) as u64);
+
+        let kind = KINDS
+            .iter()
+            .position(|&kind| kind == action.action)
+            .unwrap();
+        let location = Location::ALL
+            .iter()
+            .position(|&location| location == action.target)
+            .unwrap();
+        out.push((kind | location << 2) as u8);
+
+        write_varint(&mut out, action.unit as u64);
+        write_varint(&mut out, action.id.len() as u64);
+        out.extend_from_slice(action.id.as_bytes());
+    }
+    out
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<Action>> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        bail!("not a binary action log");
+    };
+    let (&version, mut rest) = rest
+        .split_first()
+        .ok_or_else(|| anyhow!("missing format version"))?;
+    if version != VERSION {
+        bail!("unsupported binary action log version {version}");
+    }
+
+    let mut actions = Vec::new();
+    let mut previous = 0u64;
+    while !rest.is_empty() {
+        let zigzag = read_varint(&mut rest)?;
+        let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+        let timestamp = previous.wrapping_add(delta as u64);
+        previous = timestamp;
+
+        let (&packed, tail) = rest
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated action"))?;
+        rest = tail;
+        if packed >> 4 != 0 {
+            bail!("invalid action byte {packed:#04x}");
+        }
+        let action = KINDS[usize::from(packed & 0b11)];
+        let target = Location::ALL[usize::from(packed >> 2)];
+
+        let unit = read_varint(&mut rest)? as usize;
+        let len = read_varint(&mut rest)? as usize;
+        if rest.len() < len {
+            bail!("truncated order id");
+        }
+        let (id, tail) = rest.split_at(len);
+        rest = tail;
+
+        actions.push(Action {
+            timestamp,
+            id: String::from_utf8(id.to_vec()).context("order id is not UTF-8")?,
+            action,
+            target,
+            unit,
+            seq: actions.len() as u64,
+            reason: None,
+            group: 0,
+        });
+    }
+    Ok(actions)
+}
+
+// LEB128: seven bits per byte, low bits first, high bit set on all but the last
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated varint"))?;
+        *bytes = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("varint too long")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn submitted(action: &Action) -> (u64, &str, ActionKind, Location, usize) {
+        (
+            action.timestamp,
+            &action.id,
+            action.action,
+            action.target,
+            action.unit,
+        )
+    }
+
+    // a large run's worth of actions, a quarter of a millisecond apart
+    fn ledger(len: usize) -> Vec<Action> {
+        (0..len)
+            .map(|idx| Action {
+                timestamp: 1_700_000_000_000_000 + idx as u64 * 250,
+                id: format!("{:08x}", (idx / 3) as u64 * 2_654_435_761 % (1 << 32)),
+                action: KINDS[idx % KINDS.len()],
+                target: Location::ALL[idx % Location::ALL.len()],
+                unit: idx % 3,
+                seq: idx as u64,
+                reason: None,
+                group: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn binary_logs_round_trip_and_beat_json() {
+        let mut actions = ledger(10_000);
+        // out of timestamp order and not ASCII, which the format must still carry
+        actions.swap(10, 20);
+        actions[5].id = "commande-crème".to_string();
+
+        let bytes = encode(&actions);
+        let decoded = decode(&bytes).unwrap();
+        assert!(
+            decoded
+                .iter()
+                .map(submitted)
+                .eq(actions.iter().map(submitted))
+        );
+
+        let json = serde_json::to_vec_pretty(&actions).unwrap();
+        assert!(
+            bytes.len() * 5 < json.len(),
+            "{} bytes against {} as JSON",
+            bytes.len(),
+            json.len()
+        );
+    }
+
+    #[test]
+    fn read_actions_tells_the_formats_apart() {
+        let actions = ledger(30);
+        let dir = std::env::temp_dir();
+        for format in [LogFormat::Json, LogFormat::Bin] {
+            let path = dir.join(format!("action-log-{}-{format:?}", std::process::id()));
+            write_actions(&path, &actions, format).unwrap();
+            let read = read_actions(&path);
+            fs::remove_file(&path).unwrap();
+            assert!(
+                read.unwrap()
+                    .iter()
+                    .map(submitted)
+                    .eq(actions.iter().map(submitted))
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_binary_logs_are_rejected() {
+        let bytes = encode(&ledger(3));
+        let mut newer = bytes.clone();
+        newer[MAGIC.len()] = VERSION + 1;
+        assert!(decode(&newer).is_err());
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(b"KLO").is_err());
+    }
+}
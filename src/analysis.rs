@@ -0,0 +1,494 @@
+//! Offline analysis of a run, independent of the live kitchen.
+//!
+//! [`optimal_retention`] answers, for one over-capacity instant, which orders
+//! an omniscient kitchen would have kept and where, so the discards the online
+//! policy made can be compared against the best possible ones, as
+//! [`review_capacity_discards`] does for a whole run.
+
+use crate::kitchen::{COOLER_CAPACITY, HEATER_CAPACITY, Kitchen, KitchenConfig, SHELF_CAPACITY};
+use crate::model::{Action, ActionKind, DiscardReason, Location, Order, Temp};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// cells in the exact solver's table before it falls back to the greedy one
+const EXACT_TABLE_LIMIT: usize = 4_000_000;
+
+/// An order live at the instant being analysed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveOrder {
+    pub id: String,
+    pub temp: Temp,
+    pub price: u64,
+    /// Full freshness in seconds.
+    pub freshness: u64,
+    /// Freshness left at the instant, in seconds.
+    pub remaining: f64,
+    /// When the order is picked up.
+    pub pickup_at: SystemTime,
+}
+
+impl LiveOrder {
+    // Price times the fraction of freshness left at pickup if stored at
+    // `location` from `now` until then, at the rate and on the curve `config`
+    // degrades it by there. Orders only go where they degrade at the ideal rate
+    // or on the shelf, as the kitchen places them; None anywhere else.
+    fn value_at(&self, config: &KitchenConfig, location: Location, now: SystemTime) -> Option<f64> {
+        let rate = config.rate(self.temp, location);
+        if location != Location::Shelf && rate != config.degradation.ideal_rate() {
+            return None;
+        }
+        if self.freshness == 0 {
+            return Some(0.0);
+        }
+        let wait = self.pickup_at.duration_since(now).unwrap_or_default();
+        let left = config.curve.remaining(self.remaining, wait, rate as f64);
+        if left <= config.curve.threshold() {
+            return Some(0.0);
+        }
+        Some(self.price as f64 * left / self.freshness as f64)
+    }
+}
+
+/// Slots at each location.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacities {
+    pub cooler: usize,
+    pub heater: usize,
+    pub shelf: usize,
+}
+
+impl Default for Capacities {
+    fn default() -> Self {
+        Self {
+            cooler: COOLER_CAPACITY,
+            heater: HEATER_CAPACITY,
+            shelf: SHELF_CAPACITY,
+        }
+    }
+}
+
+impl Capacities {
+    /// The capacities of a kitchen built from `config`, over all its units.
+    /// The overflow rack isn't modelled.
+    pub fn of(config: &KitchenConfig) -> Self {
+        Self {
+            cooler: config.capacity(Location::Cooler),
+            heater: config.capacity(Location::Heater),
+            shelf: config.capacity(Location::Shelf),
+        }
+    }
+}
+
+/// Which orders to keep, where, and which to discard.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetentionPlan {
+    pub keep: Vec<(String, Location)>,
+    /// Orders not worth a slot, including any that would expire before pickup.
+    pub discard: Vec<String>,
+    /// Value kept: the sum over kept orders of price times the fraction of
+    /// freshness they have left at pickup.
+    pub value: f64,
+    /// False if the input was too large to solve exactly and the plan is greedy.
+    pub exact: bool,
+}
+
+/// Chooses which of `orders`, live at `now`, to keep, and where, to maximize the
+/// value left at pickup within `capacities`, with orders degrading as a kitchen
+/// built from `config` degrades them. Each order goes to a location where it
+/// degrades at the ideal rate or to the shelf.
+///
+/// Solved exactly by dynamic programming over how full each location is,
+/// which stays cheap for the challenge's capacities and any number of orders.
+/// Inputs too large for that get the greedy plan instead: the most valuable
+/// remaining placement first.
+pub fn optimal_retention(
+    orders: &[LiveOrder],
+    capacities: Capacities,
+    config: &KitchenConfig,
+    now: SystemTime,
+) -> RetentionPlan {
+    let states = (capacities.cooler + 1) * (capacities.heater + 1) * (capacities.shelf + 1);
+    let plan = if orders.len().saturating_mul(states) <= EXACT_TABLE_LIMIT {
+        exact(orders, capacities, config, now)
+    } else {
+        greedy(orders, capacities, config, now)
+    };
+
+    let (keep, discard) = orders
+        .iter()
+        .zip(&plan.0)
+        .partition::<Vec<_>, _>(|(_, location)| location.is_some());
+    RetentionPlan {
+        keep: keep
+            .into_iter()
+            .map(|(order, location)| (order.id.clone(), location.unwrap()))
+            .collect(),
+        discard: discard
+            .into_iter()
+            .map(|(order, _)| order.id.clone())
+            .collect(),
+        value: plan.1,
+        exact: plan.2,
+    }
+}
+
+/// How a run's capacity discards compare with the offline optimum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiscardReview {
+    /// Discards made to make room.
+    pub discards: usize,
+    /// Of those, the ones [`optimal_retention`] would also have made.
+    pub optimal: usize,
+    /// Discards that couldn't be reviewed because the ledger up to them
+    /// doesn't replay.
+    pub skipped: usize,
+}
+
+impl fmt::Display for DiscardReview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} capacity discards were optimal",
+            self.optimal, self.discards
+        )?;
+        if self.skipped > 0 {
+            write!(f, ", {} not reviewed", self.skipped)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays `actions`, a run's ledger, up to each capacity discard and checks
+/// whether [`optimal_retention`] over the orders stored then, and those the
+/// discarding operation placed, keeps as much value without the discarded
+/// order as with it.
+/// `pickups` holds when each order is picked up; orders missing from it are
+/// never picked up, so they're worth nothing to keep.
+///
+/// Each discard replays the ledger from the start, so this is quadratic in the
+/// length of the run.
+pub fn review_capacity_discards(
+    config: &KitchenConfig,
+    orders: &[Order],
+    actions: &[Action],
+    pickups: &HashMap<String, SystemTime>,
+) -> DiscardReview {
+    let by_id: HashMap<&str, &Order> = orders
+        .iter()
+        .map(|order| (order.id.as_str(), order))
+        .collect();
+    let capacities = Capacities::of(config);
+    let mut review = DiscardReview::default();
+
+    for discard in actions
+        .iter()
+        .filter(|action| action.reason == Some(DiscardReason::Capacity))
+    {
+        review.discards += 1;
+        let now = UNIX_EPOCH + Duration::from_micros(discard.timestamp);
+        // the stored orders as the discarding operation found them
+        let started = actions
+            .iter()
+            .filter(|action| discard.group != 0 && action.group == discard.group)
+            .map(|action| action.timestamp)
+            .min()
+            .unwrap_or(discard.timestamp);
+        let before = UNIX_EPOCH + Duration::from_micros(started.saturating_sub(1));
+        let Ok(kitchen) = Kitchen::from_actions(config.clone(), orders, actions, before) else {
+            review.skipped += 1;
+            continue;
+        };
+
+        // an order nobody picks up is worth nothing to keep
+        let live_order = |order: &Order, remaining: f64| LiveOrder {
+            id: order.id.clone(),
+            temp: order.temp,
+            price: order.price,
+            freshness: order.freshness,
+            remaining: if pickups.contains_key(&order.id) {
+                remaining
+            } else {
+                0.0
+            },
+            pickup_at: pickups.get(&order.id).copied().unwrap_or(now),
+        };
+        let mut live = Vec::new();
+        kitchen.for_each_stored(now, |id, _, remaining| {
+            live.push(live_order(by_id[id], remaining));
+        });
+        let placed = actions.iter().filter(|action| {
+            discard.group != 0
+                && action.group == discard.group
+                && action.action == ActionKind::Place
+        });
+        for action in placed {
+            let order = by_id[action.id.as_str()];
+            live.push(live_order(order, order.freshness as f64));
+        }
+
+        // optimal if the best plan without the discarded order is as good as
+        // the best plan overall, however ties between orders fall
+        let best = optimal_retention(&live, capacities, config, now);
+        live.retain(|order| order.id != discard.id);
+        let without = optimal_retention(&live, capacities, config, now);
+        if without.value >= best.value - 1e-9 * best.value.abs().max(1.0) {
+            review.optimal += 1;
+        }
+    }
+    review
+}
+
+const LOCATIONS: [Location; 3] = [Location::Cooler, Location::Heater, Location::Shelf];
+
+// where each order goes (None to discard it), the value kept, and whether it's exact
+type Assignment = (Vec<Option<Location>>, f64, bool);
+
+fn exact(
+    orders: &[LiveOrder],
+    capacities: Capacities,
+    config: &KitchenConfig,
+    now: SystemTime,
+) -> Assignment {
+    let (heaters, shelves) = (capacities.heater + 1, capacities.shelf + 1);
+    let states = (capacities.cooler + 1) * heaters * shelves;
+    let index = |used: [usize; 3]| (used[0] * heaters + used[1]) * shelves + used[2];
+    let limits = [capacities.cooler, capacities.heater, capacities.shelf];
+
+    // best value for each count of used cooler, heater and shelf slots so far,
+    // and the choice that got there for each order: 0 discards, 1 + i uses LOCATIONS[i]
+    let mut best = vec![f64::NEG_INFINITY; states];
+    best[0] = 0.0;
+    let mut choices = vec![vec![0u8; states]; orders.len()];
+
+    for (order_idx, order) in orders.iter().enumerate() {
+        let mut next = best.clone();
+        for cooler in 0..=capacities.cooler {
+            for heater in 0..=capacities.heater {
+                for shelf in 0..=capacities.shelf {
+                    let used = [cooler, heater, shelf];
+                    let value = best[index(used)];
+                    if value == f64::NEG_INFINITY {
+                        continue;
+                    }
+                    for (slot, &location) in LOCATIONS.iter().enumerate() {
+                        let Some(gain) = order.value_at(config, location, now) else {
+                            continue;
+                        };
+                        if used[slot] == limits[slot] || gain <= 0.0 {
+                            continue;
+                        }
+                        let mut after = used;
+                        after[slot] += 1;
+                        if value + gain > next[index(after)] {
+                            next[index(after)] = value + gain;
+                            choices[order_idx][index(after)] = slot as u8 + 1;
+                        }
+                    }
+                }
+            }
+        }
+        best = next;
+    }
+
+    let (mut state, value) = best
+        .iter()
+        .copied()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap();
+    let mut assignment = vec![None; orders.len()];
+    for order_idx in (0..orders.len()).rev() {
+        let choice = choices[order_idx][state];
+        if choice == 0 {
+            continue;
+        }
+        let location = LOCATIONS[usize::from(choice - 1)];
+        assignment[order_idx] = Some(location);
+        state -= match location {
+            Location::Cooler => heaters * shelves,
+            Location::Heater => shelves,
+            _ => 1,
+        };
+    }
+    (assignment, value, true)
+}
+
+fn greedy(
+    orders: &[LiveOrder],
+    capacities: Capacities,
+    config: &KitchenConfig,
+    now: SystemTime,
+) -> Assignment {
+    let mut free = [capacities.cooler, capacities.heater, capacities.shelf];
+    let mut placements: Vec<(usize, usize, f64)> = orders
+        .iter()
+        .enumerate()
+        .flat_map(|(order_idx, order)| {
+            LOCATIONS
+                .iter()
+                .enumerate()
+                .filter_map(move |(slot, &location)| {
+                    let value = order.value_at(config, location, now)?;
+                    (value > 0.0).then_some((order_idx, slot, value))
+                })
+        })
+        .collect();
+    placements.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    let mut assignment = vec![None; orders.len()];
+    let mut value = 0.0;
+    for (order_idx, slot, gain) in placements {
+        if assignment[order_idx].is_none() && free[slot] > 0 {
+            free[slot] -= 1;
+            assignment[order_idx] = Some(LOCATIONS[slot]);
+            value += gain;
+        }
+    }
+    (assignment, value, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitchen::DegradationModel;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    fn at(secs: f64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_secs_f64(secs)
+    }
+
+    fn live(id: &str, temp: Temp, price: u64, remaining: f64, pickup_in: f64) -> LiveOrder {
+        LiveOrder {
+            id: id.to_string(),
+            temp,
+            price,
+            freshness: 60,
+            remaining,
+            pickup_at: at(pickup_in),
+        }
+    }
+
+    // the best value over every way of keeping or discarding each order
+    fn brute_force(orders: &[LiveOrder], config: &KitchenConfig, free: [usize; 3]) -> f64 {
+        let Some((order, rest)) = orders.split_first() else {
+            return 0.0;
+        };
+        let mut best = brute_force(rest, config, free);
+        for (slot, &location) in LOCATIONS.iter().enumerate() {
+            if free[slot] == 0 {
+                continue;
+            }
+            if let Some(value) = order.value_at(config, location, at(0.0)) {
+                let mut after = free;
+                after[slot] -= 1;
+                best = best.max(value + brute_force(rest, config, after));
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn exact_plans_match_brute_force() {
+        let config = KitchenConfig::default();
+        let capacities = Capacities {
+            cooler: 1,
+            heater: 2,
+            shelf: 2,
+        };
+        let temps = [Temp::Hot, Temp::Cold, Temp::Room, Temp::Any];
+        let mut rng = StdRng::seed_from_u64(605);
+        for _ in 0..200 {
+            let orders: Vec<LiveOrder> = (0..7)
+                .map(|idx| {
+                    live(
+                        &idx.to_string(),
+                        temps[rng.random_range(0..temps.len())],
+                        rng.random_range(1..100),
+                        rng.random_range(1.0..60.0),
+                        rng.random_range(0.0..40.0),
+                    )
+                })
+                .collect();
+            let plan = optimal_retention(&orders, capacities, &config, at(0.0));
+            let free = [capacities.cooler, capacities.heater, capacities.shelf];
+            let expected = brute_force(&orders, &config, free);
+            assert!(plan.exact);
+            assert!(
+                (plan.value - expected).abs() < 1e-9,
+                "{plan:?} vs {expected}"
+            );
+            assert_eq!(plan.keep.len() + plan.discard.len(), orders.len());
+        }
+    }
+
+    #[test]
+    fn values_follow_the_configured_rates() {
+        // only a shelf slot, for a hot order picked up in 10 seconds with 30 left
+        let capacities = Capacities {
+            cooler: 0,
+            heater: 0,
+            shelf: 1,
+        };
+        let orders = [live("hot", Temp::Hot, 60, 30.0, 10.0)];
+
+        let plan = optimal_retention(&orders, capacities, &KitchenConfig::default(), at(0.0));
+        assert_eq!(plan.keep, [("hot".to_string(), Location::Shelf)]);
+        // twice as fast off the heater: 20 of the 30 seconds gone at pickup
+        assert!((plan.value - 10.0).abs() < 1e-9);
+
+        let faster = KitchenConfig {
+            degradation: DegradationModel::Flat {
+                ideal: 1,
+                non_ideal: 3,
+            },
+            ..KitchenConfig::default()
+        };
+        let plan = optimal_retention(&orders, capacities, &faster, at(0.0));
+        assert!(plan.keep.is_empty());
+        assert_eq!(plan.discard, ["hot"]);
+    }
+
+    // a full shelf of room orders picked up at 100s, one of them `soon` picked
+    // up at 15s, then one more room order, which evicts `soon` as it expires first
+    fn review_eviction(soon_price: u64) -> DiscardReview {
+        let kitchen = Kitchen::with_config(KitchenConfig::default());
+        let mut orders = Vec::new();
+        let mut pickups = HashMap::new();
+        for idx in 0..=SHELF_CAPACITY {
+            let (id, freshness, price, pickup) = match idx {
+                0 => ("soon".to_string(), 20, soon_price, 15.0),
+                _ => (idx.to_string(), 300, 10, 100.0),
+            };
+            let order = Order {
+                id: id.clone(),
+                name: id.clone(),
+                temp: Temp::Room,
+                price,
+                freshness,
+                deadline_secs: None,
+            };
+            let placed_at = if idx == SHELF_CAPACITY { 1.0 } else { 0.0 };
+            kitchen.place_order(order.clone(), at(placed_at));
+            orders.push(order);
+            pickups.insert(id, at(pickup));
+        }
+        let actions = kitchen.get_actions();
+        assert_eq!(kitchen.action_counts().discard_reasons.capacity, 1);
+        review_capacity_discards(kitchen.config(), &orders, &actions, &pickups)
+    }
+
+    #[test]
+    fn reviews_tell_costly_evictions_from_cheap_ones() {
+        // worth a quarter of its price at pickup, and the cheapest order to lose
+        let cheap = review_eviction(10);
+        assert_eq!((cheap.discards, cheap.optimal, cheap.skipped), (1, 1, 0));
+        // worth far more than any order kept in its place
+        let costly = review_eviction(100);
+        assert_eq!((costly.discards, costly.optimal, costly.skipped), (1, 0, 0));
+        assert_eq!(costly.to_string(), "0 of 1 capacity discards were optimal");
+    }
+}
@@ -1,62 +1,44 @@
-#![allow(dead_code)]
+//! HTTP client for the challenge server.
 
+use crate::info;
 use anyhow::Result;
-use anyhow::anyhow;
+use anyhow::{Context, anyhow, bail};
+use flate2::Compression;
+use flate2::write::GzEncoder;
 use rand::Rng;
-use reqwest::blocking::Client as ReqwestClient;
-use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue};
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use reqwest::StatusCode;
+use reqwest::blocking::{Client as ReqwestClient, RequestBuilder};
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderValue};
 use std::collections::HashMap;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::io::Write;
+use std::time::Duration;
 
-pub const MAX_SEED: u64 = 1 << 63;
-pub const HTTP_TIMEOUT_SECS: u64 = 5;
+// the data model used to live here; re-exported so existing paths keep working
+#[allow(unused_imports)]
+pub use crate::model::{
+    Action, ActionKind, ChallengeClient, DiscardReason, Location, MAX_SEED, Order, Problem,
+    SolveResult, Temp, Unit, solve_body,
+};
 
-pub const PLACE: &str = "place";
-pub const MOVE: &str = "move";
-pub const PICKUP: &str = "pickup";
-pub const DISCARD: &str = "discard";
-
-#[derive(Debug, Clone, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub struct Action {
-    pub timestamp: u64, // unix timestamp in microseconds
-    pub id: String,
-    pub action: String,
-    pub target: String,
-}
+pub const HTTP_TIMEOUT_SECS: u64 = 5;
 
-pub const HOT: &str = "hot";
-pub const COLD: &str = "cold";
-pub const ROOM: &str = "room";
-
-pub const HEATER: &str = "heater";
-pub const COOLER: &str = "cooler";
-pub const SHELF: &str = "shelf";
-
-#[derive(Debug, Clone, Deserialize)]
-pub struct Order {
-    pub id: String,
-    pub name: String,
-    pub temp: String,
-    #[serde(default)]
-    pub price: u64,
-    pub freshness: u64, // in seconds
+/// How requests to the challenge server are authenticated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    /// The token as the `auth` query parameter, as the challenge server expects.
+    QueryParam(String),
+    /// The token in an `Authorization: Bearer` header.
+    BearerHeader(String),
+    /// A user and password in an `Authorization: Basic` header.
+    BasicHeader(String, String),
 }
 
-impl Action {
-    pub fn new(id: &str, action_type: &str, target: &str, timestamp: SystemTime) -> Self {
-        Self {
-            action: action_type.to_string(),
-            id: id.to_string(),
-            target: target.to_string(),
-            timestamp: timestamp
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_micros()
-                .try_into()
-                .unwrap(),
+impl Auth {
+    fn apply(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Auth::QueryParam(token) => request.query(&[("auth", token)]),
+            Auth::BearerHeader(token) => request.bearer_auth(token),
+            Auth::BasicHeader(user, pass) => request.basic_auth(user, Some(pass)),
         }
     }
 }
@@ -65,28 +47,66 @@ impl Action {
 pub struct Client {
     client: ReqwestClient,
     endpoint: String,
-    auth: String,
+    auth: Auth,
+    compress: bool,
 }
 
 impl Client {
-    pub fn new(endpoint: &str, auth: &str) -> Self {
+    pub fn new(endpoint: &str, auth: Auth) -> Self {
         Self {
             client: ReqwestClient::new(),
             endpoint: endpoint.to_string(),
-            auth: auth.to_string(),
+            auth,
+            compress: false,
         }
     }
 
-    pub fn challenge(&mut self, name: &str, seed: u64) -> Result<(Vec<Order>, String)> {
-        let seed = (if seed == 0 {
+    /// Gzips solve request bodies, sent with `Content-Encoding: gzip`.
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Checks that the endpoint is reachable and accepts the auth token, without
+    /// generating a problem.
+    pub fn health_check(&self) -> Result<()> {
+        let response = self
+            .auth
+            .apply(
+                self.client
+                    .head(format!("{}/interview/challenge/new", &self.endpoint)),
+            )
+            .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
+            .send()
+            .with_context(|| format!("endpoint {} unreachable", self.endpoint))?;
+
+        let status = response.status();
+        match status {
+            _ if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                Err(anyhow!("auth rejected by {} ({status})", self.endpoint))
+            }
+            _ if status.is_server_error() => {
+                Err(anyhow!("server error from {} ({status})", self.endpoint))
+            }
+            _ => Err(anyhow!(
+                "unexpected response from {} ({status})",
+                self.endpoint
+            )),
+        }
+    }
+}
+
+impl ChallengeClient for Client {
+    fn challenge(&mut self, name: &str, seed: u64) -> Result<Problem> {
+        let seed = if seed == 0 {
             rand::rng().random_range(0..MAX_SEED)
         } else {
             seed
-        })
-        .to_string();
+        };
 
         let mut query_params: HashMap<&'static str, String> =
-            HashMap::from([("seed", seed), ("auth", self.auth.clone())]);
+            HashMap::from([("seed", seed.to_string())]);
 
         if !name.is_empty() {
             query_params.insert("name", name.to_string());
@@ -98,10 +118,20 @@ impl Client {
         )?;
 
         let response = self
-            .client
-            .get(url.clone())
+            .auth
+            .apply(self.client.get(url.clone()))
             .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
-            .send()?;
+            .send()
+            .with_context(|| format!("failed to fetch a problem from {}", self.endpoint))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            bail!(
+                "fetching a problem from {} failed ({status}): {}",
+                self.endpoint,
+                response.text().unwrap_or_default().trim()
+            );
+        }
 
         let test_id = response
             .headers()
@@ -109,46 +139,235 @@ impl Client {
             .and_then(|v| v.to_str().ok().map(ToString::to_string))
             .unwrap_or_default();
 
-        let orders = response.json()?;
+        let orders = response
+            .json()
+            .with_context(|| format!("failed to parse the orders of test problem {test_id}"))?;
 
-        println!("Fetched new test problem, id={}: {}", test_id, url);
-        Ok((orders, test_id))
+        info!("Fetched new test problem, id={}: {}", test_id, url);
+        Ok(Problem {
+            orders,
+            test_id,
+            seed,
+        })
     }
 
-    pub fn solve(
+    fn solve(
         &mut self,
         test_id: &str,
         rate: Duration,
         min: Duration,
         max: Duration,
         actions: &[Action],
-    ) -> Result<String> {
-        let query = HashMap::from([("auth", &self.auth)]);
-
+    ) -> Result<SolveResult> {
         let mut headers = HeaderMap::new();
-        headers.insert("x-test-id", HeaderValue::from_str(test_id)?);
-        headers.insert(CONTENT_TYPE, HeaderValue::from_str("application/json")?);
-
-        let body = json!({
-            "options": {
-                "rate": rate.as_micros(),
-                "min": min.as_micros(),
-                "max": max.as_micros(),
-            },
-            "actions": actions
-        });
+        headers.insert(
+            "x-test-id",
+            HeaderValue::from_str(test_id)
+                .with_context(|| format!("test id {test_id:?} is not a valid header"))?,
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        let mut body = serde_json::to_vec(&solve_body(rate, min, max, actions))?;
+        if self.compress {
+            let uncompressed = body.len();
+            body = gzip(&body).context("failed to gzip the solve request")?;
+            headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            info!(
+                "Compressed solve request from {uncompressed} to {} bytes",
+                body.len()
+            );
+        }
 
         let response = self
-            .client
-            .post(format!("{}/interview/challenge/solve", &self.endpoint))
+            .auth
+            .apply(
+                self.client
+                    .post(format!("{}/interview/challenge/solve", &self.endpoint)),
+            )
             .headers(headers)
-            .query(&query)
-            .json(&body)
+            .body(body)
             .timeout(Duration::from_secs(HTTP_TIMEOUT_SECS))
-            .send()?;
+            .send()
+            .with_context(|| format!("failed to submit the solution for test {test_id}"))?;
+
+        let status = response.status();
+        let raw = response.text().with_context(|| {
+            format!("failed to read the solve response for test {test_id} ({status})")
+        })?;
+        if !status.is_success() {
+            bail!("solving test {test_id} failed ({status}): {}", raw.trim());
+        }
+        Ok(SolveResult::parse(raw))
+    }
+}
 
-        response
-            .text()
-            .map_err(|_| anyhow!("failed to validate solution"))
+fn gzip(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    // an endpoint that answers one request with `status` and nothing else
+    fn respond_once(status: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            let response =
+                format!("HTTP/1.1 {status}\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn health_check_keeps_why_an_endpoint_is_unreachable() {
+        // nothing listens on a port just given back
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let endpoint = format!("http://{addr}");
+        let client = Client::new(&endpoint, Auth::QueryParam("token".to_string()));
+
+        let err = client.health_check().unwrap_err();
+        assert_eq!(err.to_string(), format!("endpoint {endpoint} unreachable"));
+        assert!(err.chain().count() > 1, "lost the cause: {err:#}");
+    }
+
+    #[test]
+    fn health_check_tells_rejected_auth_from_server_errors() {
+        let auth = Auth::QueryParam("token".to_string());
+        let endpoint = respond_once("403 Forbidden");
+        let err = Client::new(&endpoint, auth.clone())
+            .health_check()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("auth rejected by"), "{err}");
+
+        let endpoint = respond_once("503 Service Unavailable");
+        let err = Client::new(&endpoint, auth).health_check().unwrap_err();
+        assert!(err.to_string().starts_with("server error from"), "{err}");
+    }
+
+    #[test]
+    fn gzip_shrinks_a_large_solve_body() {
+        let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let actions: Vec<Action> = (0..5_000u64)
+            .map(|idx| {
+                let at: SystemTime = start + Duration::from_millis(idx * 250);
+                let kind =
+                    [ActionKind::Place, ActionKind::Move, ActionKind::Pickup][idx as usize % 3];
+                let target =
+                    [Location::Cooler, Location::Heater, Location::Shelf][idx as usize % 3];
+                Action::new(&format!("order-{}", idx / 3), kind, target, at)
+            })
+            .collect();
+        let second = Duration::from_secs(1);
+        let body =
+            serde_json::to_vec(&solve_body(second, second * 4, second * 8, &actions)).unwrap();
+
+        let compressed = gzip(&body).unwrap();
+        // action ledgers are repetitive JSON, so gzip should do far better than this
+        assert!(
+            compressed.len() * 5 < body.len(),
+            "{} bytes gzipped to {}",
+            body.len(),
+            compressed.len()
+        );
+        let mut decompressed = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, body);
+    }
+
+    // a real client against the in-process mock server
+    #[cfg(feature = "test-server")]
+    mod against_mock_server {
+        use super::*;
+        use crate::mock_server::{ServerHandle, spawn_mock_server};
+
+        fn server() -> (String, ServerHandle) {
+            let (url, handle) = spawn_mock_server().unwrap();
+            (url.as_str().trim_end_matches('/').to_string(), handle)
+        }
+
+        fn auths() -> [Auth; 3] {
+            [
+                Auth::QueryParam("token".to_string()),
+                Auth::BearerHeader("token".to_string()),
+                Auth::BasicHeader("user".to_string(), "pass".to_string()),
+            ]
+        }
+
+        #[test]
+        fn every_auth_mode_fetches_a_problem() {
+            let (endpoint, _server) = server();
+            for auth in auths() {
+                let mut client = Client::new(&endpoint, auth.clone());
+                client.health_check().unwrap();
+                let problem = client.challenge("", 7).unwrap();
+                assert_eq!(problem.test_id, "mock-7", "{auth:?}");
+                assert_eq!(problem.seed, 7);
+                assert_eq!(problem.orders.len(), 40);
+                assert!(problem.orders.iter().all(|order| order.validate().is_ok()));
+            }
+        }
+
+        #[test]
+        fn missing_auth_is_refused() {
+            let (endpoint, _server) = server();
+            let mut client = Client::new(&endpoint, Auth::QueryParam(String::new()));
+            let err = client.health_check().unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("auth rejected by {endpoint} (401 Unauthorized)")
+            );
+            let err = client.challenge("", 7).unwrap_err();
+            assert!(err.to_string().contains("401"), "{err:#}");
+        }
+
+        #[test]
+        fn solutions_arrive_with_and_without_compression() {
+            let (endpoint, _server) = server();
+            let start = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+            for compress in [false, true] {
+                let mut client =
+                    Client::new(&endpoint, auths()[0].clone()).with_compression(compress);
+                let problem = client.challenge("", 3).unwrap();
+                let actions: Vec<Action> = problem
+                    .orders
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, order)| {
+                        let at = start + Duration::from_millis(idx as u64);
+                        Action::new(&order.id, ActionKind::Place, Location::Shelf, at)
+                    })
+                    .collect();
+                let second = Duration::from_secs(1);
+                let result = client
+                    .solve(&problem.test_id, second, second * 4, second * 8, &actions)
+                    .unwrap();
+                // the server read the body, and echoed the test id it was sent
+                assert_eq!(
+                    result.raw, "pass: mock score 100 for test mock-3 (40 actions)",
+                    "compress: {compress}"
+                );
+            }
+        }
     }
 }
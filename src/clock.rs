@@ -0,0 +1,98 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Hands out strictly increasing microsecond timestamps across threads.
+///
+/// Each call returns the provided timestamp, or one microsecond past the last
+/// value handed out if the provided one would not be strictly later.
+///
+/// The one exception is `u64::MAX`: once it has been handed out, every later
+/// call returns it again instead of wrapping around to zero.
+#[derive(Debug, Default)]
+pub struct MonotonicClock {
+    last: AtomicU64,
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next(&self, provided: u64) -> u64 {
+        let mut last = self.last.load(Ordering::Acquire);
+        loop {
+            // saturate rather than wrap; a clock at u64::MAX microseconds is half a
+            // million years out, so repeating it is the lesser evil
+            let candidate = provided.max(last.saturating_add(1));
+
+            match self.last.compare_exchange_weak(
+                last,
+                candidate,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return candidate,
+                Err(actual) => last = actual,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn reset(&self) {
+        self.last.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn concurrent_callers_never_share_a_timestamp() {
+        const THREADS: usize = 8;
+        const CALLS: usize = 10_000;
+        let clock = Arc::new(MonotonicClock::new());
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let clock = clock.clone();
+                thread::spawn(move || {
+                    // every thread keeps offering timestamps the others have passed
+                    (0..CALLS)
+                        .map(|call| clock.next((call * THREADS + thread) as u64 / 2))
+                        .collect::<Vec<u64>>()
+                })
+            })
+            .collect();
+
+        let mut seen = HashSet::new();
+        for handle in handles {
+            let stamps = handle.join().unwrap();
+            assert!(stamps.windows(2).all(|pair| pair[0] < pair[1]));
+            for stamp in stamps {
+                assert!(seen.insert(stamp), "{stamp} handed out twice");
+            }
+        }
+        assert_eq!(seen.len(), THREADS * CALLS);
+    }
+
+    #[test]
+    fn later_timestamps_are_kept_and_earlier_ones_nudged() {
+        let clock = MonotonicClock::new();
+        assert_eq!(clock.next(100), 100);
+        assert_eq!(clock.next(100), 101);
+        assert_eq!(clock.next(50), 102);
+        assert_eq!(clock.next(500), 500);
+    }
+
+    #[test]
+    fn the_clock_saturates_at_the_maximum() {
+        let clock = MonotonicClock::new();
+        assert_eq!(clock.next(u64::MAX - 1), u64::MAX - 1);
+        assert_eq!(clock.next(0), u64::MAX);
+        assert_eq!(clock.next(0), u64::MAX);
+        assert_eq!(clock.next(u64::MAX), u64::MAX);
+    }
+}
@@ -0,0 +1,376 @@
+//! Compares two action logs for the same problem and seed, order by order.
+
+use crate::model::{Action, ActionKind};
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+// what one log did with one order; times are microseconds since the log's first action
+#[derive(Debug, Default)]
+struct Handling {
+    placed_at: Option<u64>,
+    outcome: Option<(ActionKind, u64)>,
+    moves: usize,
+}
+
+impl Handling {
+    fn picked_up(&self) -> bool {
+        matches!(self.outcome, Some((ActionKind::Pickup, _)))
+    }
+}
+
+fn handlings(actions: &[Action]) -> BTreeMap<&str, Handling> {
+    let start = actions.iter().map(|action| action.timestamp).min();
+    let mut handlings: BTreeMap<&str, Handling> = BTreeMap::new();
+    for action in actions {
+        let at = action.timestamp - start.unwrap_or_default();
+        let handling = handlings.entry(&action.id).or_default();
+        match action.action {
+            ActionKind::Place => handling.placed_at = Some(at),
+            ActionKind::Move => handling.moves += 1,
+            ActionKind::Pickup | ActionKind::Discard => {
+                handling.outcome = Some((action.action, at))
+            }
+        }
+    }
+    handlings
+}
+
+/// One way the second log handled an order differently from the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderDiff {
+    /// Picked up in one log but discarded, or never resolved, in the other.
+    /// None means the log has no pickup or discard for the order.
+    Outcome {
+        id: String,
+        before: Option<ActionKind>,
+        after: Option<ActionKind>,
+    },
+    Moves {
+        id: String,
+        before: usize,
+        after: usize,
+    },
+    /// The action happened this many microseconds later (negative: earlier)
+    /// relative to the start of its log.
+    Shifted {
+        id: String,
+        action: ActionKind,
+        micros: i64,
+    },
+}
+
+impl fmt::Display for OrderDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = |kind: &Option<ActionKind>| kind.map_or("unresolved", ActionKind::as_str);
+        match self {
+            OrderDiff::Outcome { id, before, after } => {
+                write!(f, "{id}: {} -> {}", outcome(before), outcome(after))
+            }
+            OrderDiff::Moves { id, before, after } => {
+                write!(f, "{id}: {before} moves -> {after}")
+            }
+            OrderDiff::Shifted { id, action, micros } => {
+                let shift = Duration::from_micros(micros.unsigned_abs());
+                let direction = if *micros < 0 { "earlier" } else { "later" };
+                write!(f, "{id}: {action} {shift:?} {direction}")
+            }
+        }
+    }
+}
+
+/// Every per-order difference between two logs, with how many orders got
+/// better or worse: an order improved if only the second log picked it up,
+/// and regressed if only the first did.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    /// Orders appearing in either log.
+    pub orders: usize,
+    pub diffs: Vec<OrderDiff>,
+    pub improved: usize,
+    pub regressed: usize,
+    /// Total price of the improved and regressed orders, when prices are known.
+    pub improved_value: Option<u64>,
+    pub regressed_value: Option<u64>,
+    pub tolerance: Duration,
+}
+
+impl DiffReport {
+    fn count(&self, kind: fn(&OrderDiff) -> bool) -> usize {
+        self.diffs.iter().filter(|diff| kind(diff)).count()
+    }
+}
+
+impl fmt::Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diff in &self.diffs {
+            writeln!(f, "{diff}")?;
+        }
+        write!(
+            f,
+            "{} orders: {} handled differently, {} moved differently, {} shifted more than {:?}; {} improved",
+            self.orders,
+            self.count(|diff| matches!(diff, OrderDiff::Outcome { .. })),
+            self.count(|diff| matches!(diff, OrderDiff::Moves { .. })),
+            self.count(|diff| matches!(diff, OrderDiff::Shifted { .. })),
+            self.tolerance,
+            self.improved,
+        )?;
+        if let Some(value) = self.improved_value {
+            write!(f, " (${value})")?;
+        }
+        write!(f, ", {} regressed", self.regressed)?;
+        if let Some(value) = self.regressed_value {
+            write!(f, " (${value})")?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares `after` against `before`, order by order. Timestamps are taken
+/// relative to each log's first action, since two runs of the same problem
+/// start at different times; shifts within `tolerance` aren't reported. With
+/// `prices`, improved and regressed orders are also totalled by price; orders
+/// missing from it count as free.
+pub fn diff_actions(
+    before: &[Action],
+    after: &[Action],
+    prices: Option<&HashMap<String, u64>>,
+    tolerance: Duration,
+) -> DiffReport {
+    let before = handlings(before);
+    let after = handlings(after);
+    let mut ids: Vec<&str> = before.keys().chain(after.keys()).copied().collect();
+    ids.sort_unstable();
+    ids.dedup();
+
+    let missing = Handling::default();
+    let price = |id: &str| prices.and_then(|prices| prices.get(id)).copied();
+    let mut report = DiffReport {
+        orders: ids.len(),
+        improved_value: prices.map(|_| 0),
+        regressed_value: prices.map(|_| 0),
+        tolerance,
+        ..DiffReport::default()
+    };
+
+    for id in ids {
+        let old = before.get(id).unwrap_or(&missing);
+        let new = after.get(id).unwrap_or(&missing);
+
+        let (old_kind, new_kind) = (
+            old.outcome.map(|(kind, _)| kind),
+            new.outcome.map(|(kind, _)| kind),
+        );
+        if old_kind != new_kind {
+            report.diffs.push(OrderDiff::Outcome {
+                id: id.to_string(),
+                before: old_kind,
+                after: new_kind,
+            });
+            if new.picked_up() && !old.picked_up() {
+                report.improved += 1;
+                if let Some(value) = &mut report.improved_value {
+                    *value += price(id).unwrap_or_default();
+                }
+            } else if old.picked_up() && !new.picked_up() {
+                report.regressed += 1;
+                if let Some(value) = &mut report.regressed_value {
+                    *value += price(id).unwrap_or_default();
+                }
+            }
+        }
+
+        if old.moves != new.moves {
+            report.diffs.push(OrderDiff::Moves {
+                id: id.to_string(),
+                before: old.moves,
+                after: new.moves,
+            });
+        }
+
+        let mut shifts = vec![(ActionKind::Place, old.placed_at, new.placed_at)];
+        if old_kind == new_kind
+            && let (Some((kind, old_at)), Some((_, new_at))) = (old.outcome, new.outcome)
+        {
+            shifts.push((kind, Some(old_at), Some(new_at)));
+        }
+        for (action, old_at, new_at) in shifts {
+            let (Some(old_at), Some(new_at)) = (old_at, new_at) else {
+                continue;
+            };
+            let micros = new_at as i64 - old_at as i64;
+            if Duration::from_micros(micros.unsigned_abs()) > tolerance {
+                report.diffs.push(OrderDiff::Shifted {
+                    id: id.to_string(),
+                    action,
+                    micros,
+                });
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Location;
+
+    const START: u64 = 1_700_000_000_000_000;
+
+    // `at` is microseconds after the log's start
+    fn action(at: u64, id: &str, action: ActionKind) -> Action {
+        Action {
+            timestamp: START + at,
+            id: id.to_string(),
+            action,
+            target: Location::Shelf,
+            unit: 0,
+            seq: 0,
+            reason: None,
+            group: 0,
+        }
+    }
+
+    fn outcome(id: &str, before: Option<ActionKind>, after: Option<ActionKind>) -> OrderDiff {
+        OrderDiff::Outcome {
+            id: id.to_string(),
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn identical_logs_differ_in_nothing() {
+        let log = [
+            action(0, "a", ActionKind::Place),
+            action(10, "a", ActionKind::Pickup),
+        ];
+        let report = diff_actions(&log, &log, None, Duration::ZERO);
+        assert_eq!(report.orders, 1);
+        assert!(report.diffs.is_empty());
+        assert_eq!((report.improved, report.regressed), (0, 0));
+    }
+
+    #[test]
+    fn pickups_gained_and_lost_are_counted_and_priced() {
+        use ActionKind::{Discard, Pickup, Place};
+        let before = [
+            action(0, "gained", Place),
+            action(1, "lost", Place),
+            action(2, "unpriced", Place),
+            action(3, "swapped", Place),
+            action(10, "gained", Discard),
+            action(11, "lost", Pickup),
+            action(12, "swapped", Discard),
+        ];
+        let after = [
+            action(0, "gained", Place),
+            action(1, "lost", Place),
+            action(2, "unpriced", Place),
+            action(3, "swapped", Place),
+            action(10, "gained", Pickup),
+            action(11, "lost", Discard),
+            action(12, "unpriced", Pickup),
+        ];
+        let prices = HashMap::from([("gained".to_string(), 30), ("lost".to_string(), 7)]);
+
+        let report = diff_actions(&before, &after, Some(&prices), Duration::ZERO);
+        assert_eq!(
+            report.diffs,
+            [
+                outcome("gained", Some(Discard), Some(Pickup)),
+                outcome("lost", Some(Pickup), Some(Discard)),
+                // discarded, then never resolved: neither picked it up
+                outcome("swapped", Some(Discard), None),
+                // orders missing from the prices count as free
+                outcome("unpriced", None, Some(Pickup)),
+            ]
+        );
+        assert_eq!((report.improved, report.regressed), (2, 1));
+        assert_eq!(report.improved_value, Some(30));
+        assert_eq!(report.regressed_value, Some(7));
+        assert!(report.to_string().ends_with(
+            "4 orders: 4 handled differently, 0 moved differently, 0 shifted more than 0ns; \
+             2 improved ($30), 1 regressed ($7)"
+        ));
+
+        let unpriced = diff_actions(&before, &after, None, Duration::ZERO);
+        assert_eq!(
+            (unpriced.improved_value, unpriced.regressed_value),
+            (None, None)
+        );
+        assert!(unpriced.to_string().ends_with("2 improved, 1 regressed"));
+    }
+
+    #[test]
+    fn orders_in_only_one_log_are_reported() {
+        use ActionKind::{Pickup, Place};
+        let before = [action(0, "old", Place), action(5, "old", Pickup)];
+        let after = [action(0, "new", Place), action(5, "new", Pickup)];
+
+        let report = diff_actions(&before, &after, None, Duration::ZERO);
+        assert_eq!(report.orders, 2);
+        assert_eq!(
+            report.diffs,
+            [
+                outcome("new", None, Some(Pickup)),
+                outcome("old", Some(Pickup), None),
+            ]
+        );
+        assert_eq!((report.improved, report.regressed), (1, 1));
+    }
+
+    #[test]
+    fn shifts_up_to_the_tolerance_are_ignored() {
+        use ActionKind::{Move, Pickup, Place};
+        let tolerance = Duration::from_millis(1);
+        let before = [
+            action(0, "a", Place),
+            action(1_000, "b", Place),
+            action(5_000, "a", Pickup),
+            action(6_000, "b", Pickup),
+        ];
+        // a's pickup comes exactly the tolerance later, b's just over it earlier
+        let after = [
+            action(0, "a", Place),
+            action(1_000, "b", Place),
+            action(2_000, "b", Move),
+            action(4_999, "b", Pickup),
+            action(6_000, "a", Pickup),
+        ];
+
+        let report = diff_actions(&before, &after, None, tolerance);
+        assert_eq!(
+            report.diffs,
+            [
+                OrderDiff::Moves {
+                    id: "b".to_string(),
+                    before: 0,
+                    after: 1,
+                },
+                OrderDiff::Shifted {
+                    id: "b".to_string(),
+                    action: Pickup,
+                    micros: -1_001,
+                },
+            ]
+        );
+        assert_eq!(report.diffs[1].to_string(), "b: pickup 1.001ms earlier");
+
+        // a later start doesn't shift anything by itself
+        let delayed: Vec<Action> = after
+            .iter()
+            .map(|action| Action {
+                timestamp: action.timestamp + 60_000_000,
+                ..action.clone()
+            })
+            .collect();
+        assert_eq!(
+            diff_actions(&before, &delayed, None, tolerance).diffs,
+            report.diffs
+        );
+    }
+}
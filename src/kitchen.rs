@@ -1,412 +1,3555 @@
-use crate::client::{Action, Order};
-use crate::client::{COLD, COOLER, DISCARD, HEATER, HOT, MOVE, PICKUP, PLACE, ROOM, SHELF};
+use crate::clock::MonotonicClock;
+use crate::info;
+#[cfg(feature = "metrics")]
+use crate::lock_stats::LockStat;
+use crate::lock_stats::{TrackedMutex, TrackedRwLock};
+use crate::model::{Action, ActionKind, DiscardReason, Location, Order, Temp, Unit};
+use crate::verify::{ActionError, validate_actions};
 
+use std::cell::{Cell, RefCell};
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+use std::fmt;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Condvar, Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-const COOLER_CAPACITY: usize = 6;
-const HEATER_CAPACITY: usize = 6;
-const SHELF_CAPACITY: usize = 12; // TODO: maybe make this configurable?
+pub const COOLER_CAPACITY: usize = 6;
+pub const HEATER_CAPACITY: usize = 6;
+pub const SHELF_CAPACITY: usize = 12; // TODO: maybe make this configurable?
 
-const DEGRADATION_RATE_IDEAL: i64 = 1;
-const DEGRADATION_RATE_NON_IDEAL: i64 = 2;
+// actions a thread buffers before merging them into the shared ledger
+const ACTION_BATCH_SIZE: usize = 32;
+// a place and a pickup or discard, plus the occasional move
+const EXPECTED_ACTIONS_PER_ORDER: usize = 3;
+
+/// Seconds of freshness an order loses per second at its ideal temperature.
+pub const DEGRADATION_RATE_IDEAL: i64 = 1;
+/// Seconds of freshness an order loses per second anywhere else, by default.
+pub const DEGRADATION_RATE_NON_IDEAL: i64 = 2;
+
+/// How a victim is chosen when the shelf is full and an order must be discarded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum EvictionStrategy {
+    /// Discard the order that expires soonest (O(log n) via the shelf queue).
+    #[default]
+    EarliestExpiry,
+    /// Discard the order with the lowest `price × remaining freshness fraction`,
+    /// i.e. the one whose loss costs the least revenue. Scans the shelf. The
+    /// arriving order counts too: if it is worth the least, it is rejected.
+    MinRevenueLoss,
+}
+
+/// How quickly an order loses freshness, in seconds of freshness per second stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DegradationModel {
+    /// One rate at the ideal temperature, another anywhere else.
+    Flat { ideal: i64, non_ideal: i64 },
+    /// Non-ideal rate grows with how far the storage temperature is from the
+    /// ideal one: `ideal + per_step × distance`, where hot/room/cold are one
+    /// step apart. A hot order in the cooler degrades faster than on the shelf.
+    Distance { ideal: i64, per_step: i64 },
+}
+
+impl Default for DegradationModel {
+    fn default() -> Self {
+        DegradationModel::Flat {
+            ideal: DEGRADATION_RATE_IDEAL,
+            non_ideal: DEGRADATION_RATE_NON_IDEAL,
+        }
+    }
+}
+
+impl DegradationModel {
+    fn rate(&self, order_temp: Temp, storage_temp: Temp) -> i64 {
+        // an order that keeps anywhere, or a location that suits anything, is always ideal
+        if order_temp == Temp::Any || storage_temp == Temp::Any {
+            return self.ideal_rate();
+        }
+
+        match *self {
+            DegradationModel::Flat { ideal, non_ideal } => {
+                if order_temp == storage_temp {
+                    ideal
+                } else {
+                    non_ideal
+                }
+            }
+            DegradationModel::Distance { ideal, per_step } => {
+                let distance = (temp_level(order_temp) - temp_level(storage_temp)).abs();
+                ideal + per_step * distance
+            }
+        }
+    }
+
+    /// Rate at the ideal temperature.
+    pub fn ideal_rate(&self) -> i64 {
+        match *self {
+            DegradationModel::Flat { ideal, .. } | DegradationModel::Distance { ideal, .. } => {
+                ideal
+            }
+        }
+    }
+
+    // rate one step away from the ideal temperature
+    fn non_ideal_rate(&self) -> i64 {
+        match *self {
+            DegradationModel::Flat { non_ideal, .. } => non_ideal,
+            DegradationModel::Distance { ideal, per_step } => ideal + per_step,
+        }
+    }
+}
+
+// position on the cold..hot scale
+fn temp_level(temp: Temp) -> i64 {
+    match temp {
+        Temp::Cold => 0,
+        Temp::Room | Temp::Any => 1,
+        Temp::Hot => 2,
+    }
+}
+
+/// Shape of freshness loss over time. The [`DegradationModel`] decides how fast
+/// an order degrades where it is stored; the curve decides what that rate does
+/// to the freshness it has left.
+pub trait DegradationCurve: fmt::Debug + Send + Sync {
+    /// Freshness (in seconds) left from `initial` after `elapsed` at `rate`.
+    fn remaining(&self, initial: f64, elapsed: Duration, rate: f64) -> f64;
+
+    /// How long until `initial` freshness reaches [`threshold`](Self::threshold) at `rate`.
+    fn time_to_expiry(&self, initial: f64, rate: f64) -> Duration;
+
+    /// Freshness at or below which an order counts as expired.
+    fn threshold(&self) -> f64 {
+        0.0
+    }
+}
+
+/// Loses `rate` seconds of freshness per second stored.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Linear;
+
+impl DegradationCurve for Linear {
+    fn remaining(&self, initial: f64, elapsed: Duration, rate: f64) -> f64 {
+        initial - elapsed.as_secs_f64() * rate
+    }
+
+    fn time_to_expiry(&self, initial: f64, rate: f64) -> Duration {
+        if initial <= self.threshold() {
+            return Duration::ZERO;
+        }
+        // a zero rate never expires
+        Duration::try_from_secs_f64((initial - self.threshold()) / rate).unwrap_or(Duration::MAX)
+    }
+}
+
+/// Loses a fixed fraction `decay` of the freshness left every second at the
+/// ideal rate, compounding faster at higher rates. Never reaches zero, so an
+/// order expires once it drops to `threshold` seconds of freshness.
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential {
+    /// Fraction of the remaining freshness lost per second, in `(0, 1)`.
+    pub decay: f64,
+    pub threshold: f64,
+}
+
+impl DegradationCurve for Exponential {
+    fn remaining(&self, initial: f64, elapsed: Duration, rate: f64) -> f64 {
+        initial * (1.0 - self.decay).powf(elapsed.as_secs_f64() * rate)
+    }
+
+    fn time_to_expiry(&self, initial: f64, rate: f64) -> Duration {
+        if initial <= self.threshold {
+            return Duration::ZERO;
+        }
+        let secs = (self.threshold / initial).ln() / ((1.0 - self.decay).ln() * rate);
+        Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+    }
+
+    fn threshold(&self) -> f64 {
+        self.threshold
+    }
+}
+
+/// Ambient temperature of each storage location. An `Any` location is ideal
+/// for every order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocationTemps {
+    pub heater: Temp,
+    pub cooler: Temp,
+    pub shelf: Temp,
+}
+
+impl Default for LocationTemps {
+    fn default() -> Self {
+        Self {
+            heater: Temp::Hot,
+            cooler: Temp::Cold,
+            shelf: Temp::Room,
+        }
+    }
+}
+
+impl LocationTemps {
+    // None for the overflow rack, which has no temperature of its own
+    fn temp_of(&self, location: Location) -> Option<Temp> {
+        match location {
+            Location::Heater => Some(self.heater),
+            Location::Cooler => Some(self.cooler),
+            Location::Shelf => Some(self.shelf),
+            Location::Overflow => None,
+        }
+    }
+}
+
+/// Moves shelf orders back to their ideal storage before a placement once the
+/// shelf is at least `high_water_mark` full (a fraction of its capacity), first
+/// discarding expired orders from the cooler and heater to make room for them.
+/// The shelf then reaches hard capacity, and forces a discard, less often.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProactiveRebalance {
+    pub high_water_mark: f64,
+}
+
+/// What to do with a new order when its ideal storage and the shelf are both full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Make room by moving or discarding a resident order.
+    #[default]
+    ForceDiscard,
+    /// Drop the new order without touching anything already stored.
+    Reject,
+    /// Block until a pickup or discard frees a slot. If none frees within
+    /// `timeout`, or before the new order would expire when it is None, handle
+    /// the order as `fallback` says instead.
+    WaitForSlot {
+        timeout: Option<Duration>,
+        fallback: WaitFallback,
+    },
+}
+
+/// What [`OverflowPolicy::WaitForSlot`] does once it gives up waiting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WaitFallback {
+    /// Drop the new order, as [`OverflowPolicy::Reject`] does.
+    #[default]
+    Reject,
+    /// Make room after all, as [`OverflowPolicy::ForceDiscard`] does.
+    ForceDiscard,
+}
+
+/// Result of [`Kitchen::place_order`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// The order was stored and a place action recorded.
+    Placed,
+    /// No slot was free and the overflow policy dropped the order, or under
+    /// [`EvictionStrategy::MinRevenueLoss`] it was the cheapest order to lose;
+    /// nothing was recorded.
+    Rejected,
+    /// The order's id was already in the kitchen, so it was ignored.
+    Ignored,
+}
+
+/// Result of [`Kitchen::pickup_order`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickupOutcome {
+    /// The order was handed out and a pickup action recorded.
+    PickedUp,
+    /// The order had expired by the time of the pickup, so it was discarded.
+    Expired,
+    /// The order was still fresh but the pickup came after its deadline, so it
+    /// was discarded.
+    Late,
+    /// The order was still fresh but below the pickup freshness threshold, so it
+    /// was discarded.
+    Discarded,
+    /// The order was already picked up; nothing was recorded.
+    AlreadyPickedUp,
+    /// The order was already discarded or cancelled; nothing was recorded.
+    AlreadyDiscarded,
+    /// No order with this id has been in the kitchen.
+    Unknown,
+}
+
+/// An internal invariant found broken by [`Kitchen::check_invariants`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantError {
+    OverCapacity {
+        unit: Unit,
+        len: usize,
+        capacity: usize,
+    },
+    StoredTwice {
+        id: String,
+    },
+    /// A shelf order the eviction queue has no entry for.
+    Unqueued {
+        id: String,
+    },
+    /// An eviction queue entry for an order that isn't on the shelf.
+    StaleQueueEntry {
+        id: String,
+    },
+    /// The location index disagrees with where the order actually is.
+    WrongIndex {
+        id: String,
+        indexed: Option<Location>,
+        actual: Option<Location>,
+    },
+}
+
+impl fmt::Display for InvariantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantError::OverCapacity {
+                unit,
+                len,
+                capacity,
+            } => write!(f, "{unit} holds {len} orders, capacity {capacity}"),
+            InvariantError::StoredTwice { id } => write!(f, "order {id} is stored twice"),
+            InvariantError::Unqueued { id } => {
+                write!(f, "shelf order {id} has no eviction queue entry")
+            }
+            InvariantError::StaleQueueEntry { id } => {
+                write!(
+                    f,
+                    "eviction queue entry for order {id}, which isn't on the shelf"
+                )
+            }
+            InvariantError::WrongIndex {
+                id,
+                indexed,
+                actual,
+            } => write!(
+                f,
+                "order {id} is indexed at {indexed:?} but stored at {actual:?}"
+            ),
+        }
+    }
+}
+
+/// Degradation state of one stored order, from [`Kitchen::inspect`].
+#[derive(Debug, Clone)]
+pub struct OrderInspection {
+    pub order: Order,
+    pub location: Location,
+    /// Where the order would degrade at the ideal rate: its current location if
+    /// that qualifies, otherwise the first of cooler, heater and shelf that does.
+    pub ideal_location: Option<Location>,
+    /// Whether the order is degrading at the ideal rate where it is.
+    pub degrading_ideally: bool,
+    /// Whether the order has ever been stored away from its ideal temperature.
+    pub ever_non_ideal: bool,
+    /// Freshness left, in seconds.
+    pub remaining_freshness: f64,
+    pub expires_at: SystemTime,
+}
+
+impl fmt::Display for OrderInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}) on the {}, {:.1}s fresh",
+            self.order.id, self.order.temp, self.location, self.remaining_freshness
+        )?;
+        match self.ideal_location {
+            _ if self.degrading_ideally && self.ever_non_ideal => {
+                write!(f, ", back where it keeps best after time away")
+            }
+            _ if self.degrading_ideally => Ok(()),
+            Some(ideal) => write!(f, ", degrading faster than on the {ideal}"),
+            None => write!(f, ", degrading faster than anywhere ideal"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KitchenConfig {
+    pub eviction: EvictionStrategy,
+    pub degradation: DegradationModel,
+    pub curve: Arc<dyn DegradationCurve>,
+    pub location_temps: LocationTemps,
+    pub overflow: OverflowPolicy,
+    /// Record actions at the timestamps they are given instead of one shared
+    /// monotonic clock, only nudged so that each order's actions, and the
+    /// actions of one placement or pickup, strictly increase.
+    /// [`Kitchen::drain_actions`] then sorts by `(timestamp, order id, recording
+    /// order)` and makes timestamps strictly increasing. Off by default, since
+    /// the live clock is what a real-time run should submit.
+    pub deterministic: bool,
+    /// Slots in the overflow rack, used only once the ideal storage and the shelf
+    /// are full. Everything there degrades at the non-ideal rate. 0 disables it.
+    pub overflow_capacity: usize,
+    /// Fraction of its full freshness an order needs left to be handed out at
+    /// pickup; unexpired orders below it are discarded instead. The default of
+    /// 0 only discards expired orders.
+    pub pickup_freshness_threshold: f64,
+    /// Relieve a nearly full shelf before each placement. None, the default,
+    /// only moves orders off the shelf after a pickup or cancellation.
+    pub proactive_rebalance: Option<ProactiveRebalance>,
+    /// What a move costs, in the same dollars as order prices. An order only
+    /// moves to the shelf to make room, or off it into its ideal storage, when
+    /// the move saves more than this: the freshness the slower of the two rates
+    /// keeps over the order's remaining life, priced by the order. Making room
+    /// counts both the order moved out and the new one moving in. None, the
+    /// default, moves whenever there is room.
+    pub move_penalty: Option<f64>,
+    /// Identical cooler, heater and shelf units, each holding the usual number
+    /// of orders. Orders go to the first unit of a location with room and can
+    /// only move whole between units. The overflow rack is always one unit.
+    pub cooler_units: usize,
+    pub heater_units: usize,
+    pub shelf_units: usize,
+    /// How many orders the kitchen will see, if known, so its ledger and
+    /// per-order maps can be allocated up front. 0 allocates as they grow.
+    pub expected_orders: usize,
+}
+
+impl Default for KitchenConfig {
+    fn default() -> Self {
+        Self {
+            eviction: EvictionStrategy::default(),
+            degradation: DegradationModel::default(),
+            curve: Arc::new(Linear),
+            location_temps: LocationTemps::default(),
+            overflow: OverflowPolicy::default(),
+            deterministic: false,
+            overflow_capacity: 0,
+            pickup_freshness_threshold: 0.0,
+            proactive_rebalance: None,
+            move_penalty: None,
+            cooler_units: 1,
+            heater_units: 1,
+            shelf_units: 1,
+            expected_orders: 0,
+        }
+    }
+}
+
+impl KitchenConfig {
+    /// Number of units of `location`.
+    pub fn units(&self, location: Location) -> usize {
+        match location {
+            Location::Cooler => self.cooler_units,
+            Location::Heater => self.heater_units,
+            Location::Shelf => self.shelf_units,
+            Location::Overflow => 1,
+        }
+    }
+
+    /// Orders each unit of `location` holds.
+    pub fn unit_capacity(&self, location: Location) -> usize {
+        match location {
+            Location::Cooler => COOLER_CAPACITY,
+            Location::Heater => HEATER_CAPACITY,
+            Location::Shelf => SHELF_CAPACITY,
+            Location::Overflow => self.overflow_capacity,
+        }
+    }
+
+    /// Orders all units of `location` hold together.
+    pub fn capacity(&self, location: Location) -> usize {
+        self.units(location) * self.unit_capacity(location)
+    }
+
+    /// Degradation rate of an order with ideal temperature `temp` stored at `location`.
+    pub fn rate(&self, temp: Temp, location: Location) -> i64 {
+        match self.location_temps.temp_of(location) {
+            Some(storage_temp) => self.degradation.rate(temp, storage_temp),
+            None => self.degradation.non_ideal_rate(),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 struct StoredOrder {
     order: Order,
+    temp: Temp,
     placed_at: SystemTime,
-    current_temp: String,
+    // placement time plus the order's deadline, if it has one; unlike
+    // `placed_at` it stays put while a placement waits for a slot
+    deadline: Option<SystemTime>,
+    // None until the order is first stored
+    location: Option<Location>,
+    // which unit of `location` holds it
+    unit: usize,
+    // freshness left (in seconds) when the order arrived at its current location, and
+    // when that was; degradation is only ever computed from here, so moves don't need the
+    // order's history
+    freshness_snapshot: f64,
+    snapshot_at: SystemTime,
+    // whether the order has ever been stored away from its ideal temperature
+    ever_non_ideal: bool,
+}
+
+impl StoredOrder {
+    fn new(order: Order, placed_at: SystemTime) -> Self {
+        Self {
+            freshness_snapshot: order.freshness as f64,
+            temp: order.temp,
+            deadline: order
+                .deadline_secs
+                .map(|secs| placed_at + Duration::from_secs(secs)),
+            order,
+            placed_at,
+            location: None,
+            unit: 0,
+            snapshot_at: placed_at,
+            ever_non_ideal: false,
+        }
+    }
+
+    // remaining freshness in seconds
+    fn remaining_freshness(&self, config: &KitchenConfig, now: SystemTime) -> f64 {
+        let elapsed = now.duration_since(self.snapshot_at).unwrap_or_default();
+        let degradation_rate = self.rate(config);
+        config
+            .curve
+            .remaining(self.freshness_snapshot, elapsed, degradation_rate as f64)
+    }
+
+    // how fast the order degrades where it is now
+    fn rate(&self, config: &KitchenConfig) -> i64 {
+        match self.location {
+            Some(location) => config.rate(self.temp, location),
+            None => config.degradation.ideal_rate(),
+        }
+    }
+
+    // remaining freshness as a fraction of the order's full freshness
+    fn freshness_fraction(&self, config: &KitchenConfig, now: SystemTime) -> f64 {
+        if self.order.freshness == 0 {
+            return 0.0;
+        }
+        self.remaining_freshness(config, now).max(0.0) / self.order.freshness as f64
+    }
+
+    // Moves the order to `unit` of `location` at `now`, settling the freshness
+    // it lost at the old location first.
+    fn relocate(
+        &mut self,
+        config: &KitchenConfig,
+        location: Location,
+        unit: usize,
+        now: SystemTime,
+    ) {
+        if self.location.is_some() {
+            self.freshness_snapshot = self.remaining_freshness(config, now);
+            self.snapshot_at = now;
+        }
+        self.location = Some(location);
+        self.unit = unit;
+        if config.rate(self.temp, location) != config.degradation.ideal_rate() {
+            self.ever_non_ideal = true;
+        }
+    }
+
+    // the unit holding the order; only called once it is stored
+    fn target(&self) -> Unit {
+        Unit {
+            location: self.location.expect("order is stored"),
+            index: self.unit,
+        }
+    }
+
+    // why this order is discarded once it has expired
+    fn expiry_reason(&self) -> DiscardReason {
+        if self.ever_non_ideal {
+            DiscardReason::ExpiredNonIdeal
+        } else {
+            DiscardReason::ExpiredIdeal
+        }
+    }
+
+    fn is_expired(&self, config: &KitchenConfig, now: SystemTime) -> bool {
+        self.remaining_freshness(config, now) <= config.curve.threshold()
+    }
+
+    // revenue we'd expect to lose by discarding this order now
+    fn expected_value(&self, config: &KitchenConfig, now: SystemTime) -> f64 {
+        self.order.price as f64 * self.freshness_fraction(config, now)
+    }
+
+    // Value saved by keeping the order at `to` rather than `from`, negative if
+    // it degrades faster there: the freshness the slower rate keeps over the
+    // order's remaining life at the faster one, priced like expected_value.
+    fn move_gain(
+        &self,
+        config: &KitchenConfig,
+        from: Location,
+        to: Location,
+        now: SystemTime,
+    ) -> f64 {
+        let from = config.rate(self.temp, from) as f64;
+        let to = config.rate(self.temp, to) as f64;
+        let value = self.expected_value(config, now);
+        if to < from {
+            value * (1.0 - to / from)
+        } else if to > from {
+            -value * (1.0 - from / to)
+        } else {
+            0.0
+        }
+    }
+}
+
+// priority queue entry
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct OrderEntry {
+    order_id: String,
+    expires_at: i64, // Unix timestamp in microseconds
+}
+
+impl Ord for OrderEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.expires_at.cmp(&other.expires_at)
+    }
+}
+
+impl PartialOrd for OrderEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// what came of trying to evict from the full shelf for an order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Eviction {
+    Evicted,
+    // a concurrent pickup emptied the shelf first
+    Empty,
+    // MinRevenueLoss: the order itself is the cheapest to lose
+    Declined,
+}
+
+/// Orders whose recorded actions don't add up to a place followed by a pickup
+/// or discard, from [`Kitchen::reconcile`]. Ids are in problem order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReconciliationReport {
+    /// Orders in the problem with no actions at all.
+    pub unplaced: Vec<String>,
+    /// Orders placed but never picked up or discarded.
+    pub unresolved: Vec<String>,
+    /// Orders whose actions can't have happened: something before the place, a
+    /// second place, or something after the pickup or discard. Ids outside the
+    /// problem are listed last.
+    pub impossible: Vec<String>,
+}
+
+impl ReconciliationReport {
+    pub fn is_clean(&self) -> bool {
+        self.unplaced.is_empty() && self.unresolved.is_empty() && self.impossible.is_empty()
+    }
+}
+
+impl fmt::Display for ReconciliationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} never placed {:?}, {} never picked up or discarded {:?}, \
+             {} with impossible actions {:?}",
+            self.unplaced.len(),
+            self.unplaced,
+            self.unresolved.len(),
+            self.unresolved,
+            self.impossible.len(),
+            self.impossible
+        )
+    }
+}
+
+/// Per-type action counts for a run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Summary {
+    pub placed: usize,
+    pub moved: usize,
+    pub picked_up: usize,
+    pub discarded: usize,
+    pub discard_reasons: DiscardCounts,
+}
+
+/// Discards broken down by [`DiscardReason`]. Discards without a recorded
+/// reason aren't counted here.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DiscardCounts {
+    pub expired_ideal: usize,
+    pub expired_non_ideal: usize,
+    pub missed: usize,
+    pub late: usize,
+    pub capacity: usize,
+    pub cancelled: usize,
+    pub stale: usize,
+}
+
+impl Summary {
+    // the binary reads the kitchen's running counters instead
+    #[allow(dead_code)]
+    pub fn from_actions(actions: &[Action]) -> Self {
+        let mut summary = Self::default();
+        for action in actions {
+            match action.action {
+                ActionKind::Place => summary.placed += 1,
+                ActionKind::Move => summary.moved += 1,
+                ActionKind::Pickup => summary.picked_up += 1,
+                ActionKind::Discard => summary.discarded += 1,
+            }
+            let reasons = &mut summary.discard_reasons;
+            match action.reason {
+                Some(DiscardReason::ExpiredIdeal) => reasons.expired_ideal += 1,
+                Some(DiscardReason::ExpiredNonIdeal) => reasons.expired_non_ideal += 1,
+                Some(DiscardReason::Missed) => reasons.missed += 1,
+                Some(DiscardReason::Late) => reasons.late += 1,
+                Some(DiscardReason::Capacity) => reasons.capacity += 1,
+                Some(DiscardReason::Cancelled) => reasons.cancelled += 1,
+                Some(DiscardReason::Stale) => reasons.stale += 1,
+                None => {}
+            }
+        }
+        summary
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reasons = &self.discard_reasons;
+        write!(
+            f,
+            "{} placed, {} moved, {} picked up, {} discarded \
+             ({} expired in ideal storage, {} expired after non-ideal storage, \
+             {} expired before pickup, {} picked up past their deadline, {} evicted for capacity, \
+             {} cancelled, {} too stale to hand out)",
+            self.placed,
+            self.moved,
+            self.picked_up,
+            self.discarded,
+            reasons.expired_ideal,
+            reasons.expired_non_ideal,
+            reasons.missed,
+            reasons.late,
+            reasons.capacity,
+            reasons.cancelled,
+            reasons.stale
+        )
+    }
+}
+
+// Lock-free running totals of the actions a kitchen has recorded, by type and
+// discard reason; the same numbers Summary::from_actions derives from a ledger.
+#[derive(Debug, Default)]
+struct ActionCounters {
+    placed: AtomicUsize,
+    moved: AtomicUsize,
+    picked_up: AtomicUsize,
+    discarded: AtomicUsize,
+    expired_ideal: AtomicUsize,
+    expired_non_ideal: AtomicUsize,
+    missed: AtomicUsize,
+    late: AtomicUsize,
+    capacity: AtomicUsize,
+    cancelled: AtomicUsize,
+    stale: AtomicUsize,
+}
+
+impl ActionCounters {
+    fn count(&self, action: &Action) {
+        let by_type = match action.action {
+            ActionKind::Place => &self.placed,
+            ActionKind::Move => &self.moved,
+            ActionKind::Pickup => &self.picked_up,
+            ActionKind::Discard => &self.discarded,
+        };
+        by_type.fetch_add(1, AtomicOrdering::Relaxed);
+
+        let by_reason = match action.reason {
+            Some(DiscardReason::ExpiredIdeal) => &self.expired_ideal,
+            Some(DiscardReason::ExpiredNonIdeal) => &self.expired_non_ideal,
+            Some(DiscardReason::Missed) => &self.missed,
+            Some(DiscardReason::Late) => &self.late,
+            Some(DiscardReason::Capacity) => &self.capacity,
+            Some(DiscardReason::Cancelled) => &self.cancelled,
+            Some(DiscardReason::Stale) => &self.stale,
+            None => return,
+        };
+        by_reason.fetch_add(1, AtomicOrdering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Summary {
+        let load = |counter: &AtomicUsize| counter.load(AtomicOrdering::Relaxed);
+        Summary {
+            placed: load(&self.placed),
+            moved: load(&self.moved),
+            picked_up: load(&self.picked_up),
+            discarded: load(&self.discarded),
+            discard_reasons: DiscardCounts {
+                expired_ideal: load(&self.expired_ideal),
+                expired_non_ideal: load(&self.expired_non_ideal),
+                missed: load(&self.missed),
+                late: load(&self.late),
+                capacity: load(&self.capacity),
+                cancelled: load(&self.cancelled),
+                stale: load(&self.stale),
+            },
+        }
+    }
+
+    #[cfg(test)]
+    fn clear(&self) {
+        for counter in [
+            &self.placed,
+            &self.moved,
+            &self.picked_up,
+            &self.discarded,
+            &self.expired_ideal,
+            &self.expired_non_ideal,
+            &self.missed,
+            &self.late,
+            &self.capacity,
+            &self.cancelled,
+            &self.stale,
+        ] {
+            counter.store(0, AtomicOrdering::Relaxed);
+        }
+    }
+}
+
+/// Per-lock acquisition counts and wait times, counted with the `metrics` feature.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    pub cooler: LockStat,
+    pub heater: LockStat,
+    pub shelf: LockStat,
+    pub shelf_queue: LockStat,
+    pub overflow: LockStat,
+    pub locations: LockStat,
+    pub actions: LockStat,
+}
+
+/// Freshness left on orders when they were picked up, as a fraction of their
+/// full freshness. Shows how much slack the policy leaves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PickupStats {
+    pub count: usize,
+    pub min: f64,
+    pub mean: f64,
+    pub max: f64,
+    /// Pickup counts in 10% buckets of remaining freshness, `[0%, 10%)` first;
+    /// the last bucket also holds fully fresh orders.
+    pub histogram: [usize; 10],
+}
+
+impl PickupStats {
+    fn from_fractions(fractions: &[f64]) -> Self {
+        if fractions.is_empty() {
+            return Self::default();
+        }
+
+        let mut stats = Self {
+            count: fractions.len(),
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            ..Self::default()
+        };
+        for &fraction in fractions {
+            stats.min = stats.min.min(fraction);
+            stats.max = stats.max.max(fraction);
+            stats.mean += fraction / fractions.len() as f64;
+            let bucket = ((fraction * 10.0) as usize).min(stats.histogram.len() - 1);
+            stats.histogram[bucket] += 1;
+        }
+        stats
+    }
+}
+
+impl fmt::Display for PickupStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pickups, freshness left min {:.0}% / mean {:.0}% / max {:.0}%, histogram {:?}",
+            self.count,
+            self.min * 100.0,
+            self.mean * 100.0,
+            self.max * 100.0,
+            self.histogram
+        )
+    }
+}
+
+/// How far placements ran behind their scheduled time. Placements that ran
+/// early count as zero.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LatencyStats {
+    pub count: usize,
+    pub min: Duration,
+    pub mean: Duration,
+    pub max: Duration,
+    pub p99: Duration,
+}
+
+impl LatencyStats {
+    fn from_latencies(latencies: &[Duration]) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+
+        let mut sorted = latencies.to_vec();
+        sorted.sort_unstable();
+        // nearest rank
+        let p99_rank = (sorted.len() * 99).div_ceil(100);
+        Self {
+            count: sorted.len(),
+            min: sorted[0],
+            mean: sorted.iter().sum::<Duration>() / sorted.len() as u32,
+            max: sorted[sorted.len() - 1],
+            p99: sorted[p99_rank - 1],
+        }
+    }
+}
+
+impl fmt::Display for LatencyStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} placements, behind schedule min {:?} / mean {:?} / max {:?} / p99 {:?}",
+            self.count, self.min, self.mean, self.max, self.p99
+        )
+    }
+}
+
+type Ledger = Arc<TrackedMutex<Vec<Action>>>;
+
+// Actions recorded on the current thread that haven't been merged into their
+// kitchen's ledger yet. Keyed by ledger so one thread can serve several kitchens.
+#[derive(Default)]
+struct ActionBuffers(Vec<(Ledger, Vec<Action>)>);
+
+impl ActionBuffers {
+    fn push(&mut self, ledger: &Ledger, action: Action) {
+        let idx = match self.0.iter().position(|(l, _)| Arc::ptr_eq(l, ledger)) {
+            Some(idx) => idx,
+            None => {
+                self.0
+                    .push((ledger.clone(), Vec::with_capacity(ACTION_BATCH_SIZE)));
+                self.0.len() - 1
+            }
+        };
+
+        let (ledger, buffer) = &mut self.0[idx];
+        buffer.push(action);
+        if buffer.len() >= ACTION_BATCH_SIZE {
+            ledger.lock().unwrap().append(buffer);
+        }
+    }
+
+    fn flush(&mut self, ledger: &Ledger) {
+        if let Some((_, buffer)) = self.0.iter_mut().find(|(l, _)| Arc::ptr_eq(l, ledger)) {
+            ledger.lock().unwrap().append(buffer);
+        }
+    }
+
+    #[cfg(test)]
+    fn discard(&mut self, ledger: &Ledger) {
+        self.0.retain(|(l, _)| !Arc::ptr_eq(l, ledger));
+    }
+}
+
+impl Drop for ActionBuffers {
+    // runs when the thread exits, so joined workers never lose actions
+    fn drop(&mut self) {
+        for (ledger, buffer) in self.0.iter_mut() {
+            if let Ok(mut actions) = ledger.lock() {
+                actions.append(buffer);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static ACTION_BUFFERS: RefCell<ActionBuffers> = RefCell::new(ActionBuffers::default());
+    // group of the kitchen operation running on the current thread, 0 outside one
+    static OPERATION_GROUP: Cell<u64> = const { Cell::new(0) };
+    // latest timestamp a deterministic kitchen gave an action of that operation
+    static OPERATION_CLOCK: Cell<u64> = const { Cell::new(0) };
+}
+
+// Ends an operation started by Kitchen::begin_operation when dropped.
+struct OperationGuard {
+    previous: u64,
 }
 
-impl StoredOrder {
-    fn get_storage_temp(storage_location: &str) -> &str {
-        match storage_location {
-            HEATER => HOT,
-            COOLER => COLD,
-            SHELF => ROOM,
-            _ => ROOM,
+impl Drop for OperationGuard {
+    fn drop(&mut self) {
+        OPERATION_GROUP.with(|group| group.set(self.previous));
+        if self.previous == 0 {
+            OPERATION_CLOCK.with(|clock| clock.set(0));
+        }
+    }
+}
+
+pub type ActionObserver = Box<dyn Fn(&Action) + Send + Sync>;
+
+// Write guards on the containers one operation needs, from Kitchen::lock_containers.
+// Asking for a location that wasn't locked is a bug and panics.
+struct Containers<'a> {
+    cooler: Option<MutexGuard<'a, VecDeque<StoredOrder>>>,
+    heater: Option<MutexGuard<'a, VecDeque<StoredOrder>>>,
+    shelf: Option<RwLockWriteGuard<'a, HashMap<String, StoredOrder>>>,
+    overflow: Option<MutexGuard<'a, VecDeque<StoredOrder>>>,
+}
+
+impl Containers<'_> {
+    fn len(&self, location: Location) -> usize {
+        let len = match location {
+            Location::Cooler => self.cooler.as_ref().map(|cooler| cooler.len()),
+            Location::Heater => self.heater.as_ref().map(|heater| heater.len()),
+            Location::Shelf => self.shelf.as_ref().map(|shelf| shelf.len()),
+            Location::Overflow => self.overflow.as_ref().map(|overflow| overflow.len()),
+        };
+        len.expect("location is not locked")
+    }
+
+    fn shelf(&mut self) -> &mut HashMap<String, StoredOrder> {
+        self.shelf.as_deref_mut().expect("shelf is not locked")
+    }
+
+    // the queue of a location other than the shelf
+    fn storage(&mut self, location: Location) -> &mut VecDeque<StoredOrder> {
+        let storage = match location {
+            Location::Cooler => self.cooler.as_deref_mut(),
+            Location::Heater => self.heater.as_deref_mut(),
+            Location::Overflow => self.overflow.as_deref_mut(),
+            Location::Shelf => panic!("the shelf is not a queue"),
+        };
+        storage.expect("location is not locked")
+    }
+}
+
+/// A thread-safe kitchen: every method takes `&self` and may be called from
+/// any number of threads at once.
+///
+/// Operations that hold several locks at once take them in this order:
+///
+/// cooler < heater < shelf < overflow < shelf_queue < locations < actions
+///
+/// Several containers are locked together with `lock_containers`, which
+/// follows the order whatever order they are asked for in. The remaining locks
+/// (`pickup_freshness`, `placement_latency`, `slot_generation`, `last_recorded`,
+/// `outcomes` and `observers`) are leaves: nothing else is taken while one is held.
+pub struct Kitchen {
+    cooler: Arc<TrackedMutex<VecDeque<StoredOrder>>>,
+    heater: Arc<TrackedMutex<VecDeque<StoredOrder>>>,
+    shelf: Arc<TrackedRwLock<HashMap<String, StoredOrder>>>,
+    shelf_queue: Arc<TrackedMutex<BinaryHeap<Reverse<OrderEntry>>>>,
+    overflow: TrackedMutex<VecDeque<StoredOrder>>,
+
+    // where every order in the kitchen is, so pickups go straight to it and
+    // duplicate ids can be rejected; None while an order is still being placed
+    locations: TrackedMutex<HashMap<String, Option<Location>>>,
+
+    // fraction of freshness left on each order at pickup
+    pickup_freshness: Mutex<Vec<f64>>,
+    // how late each scheduled placement ran
+    placement_latency: Mutex<Vec<Duration>>,
+
+    // bumped and notified whenever a slot frees up, for OverflowPolicy::WaitForSlot
+    slot_generation: Mutex<u64>,
+    slot_freed: Condvar,
+
+    // shared ledger; threads append to it in batches via ACTION_BUFFERS
+    actions: Ledger,
+    observers: RwLock<Vec<ActionObserver>>,
+
+    // make sure timestamps are monotonic
+    clock: MonotonicClock,
+    // next action sequence number
+    seq: AtomicU64,
+    // next operation group id; 0 is left for actions recorded outside an operation
+    next_group: AtomicU64,
+    counters: ActionCounters,
+    // last timestamp recorded for each order, so its actions never go backwards
+    last_recorded: Mutex<HashMap<String, u64>>,
+    // how each order that left the kitchen left it (pickup or discard), so a
+    // repeated pickup can be told apart from an unknown id; cleared by reset
+    outcomes: Mutex<HashMap<String, ActionKind>>,
+
+    config: KitchenConfig,
+}
+
+impl Kitchen {
+    // the binary always builds its kitchen from the command line's config
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::with_config(KitchenConfig::default())
+    }
+
+    pub fn with_config(config: KitchenConfig) -> Self {
+        let orders = config.expected_orders;
+        let [cooler_capacity, heater_capacity, shelf_capacity] =
+            [Location::Cooler, Location::Heater, Location::Shelf].map(|l| config.capacity(l));
+        Self {
+            cooler: Arc::new(TrackedMutex::new(VecDeque::with_capacity(cooler_capacity))),
+            heater: Arc::new(TrackedMutex::new(VecDeque::with_capacity(heater_capacity))),
+            shelf: Arc::new(TrackedRwLock::new(HashMap::with_capacity(shelf_capacity))),
+            shelf_queue: Arc::new(TrackedMutex::new(BinaryHeap::with_capacity(shelf_capacity))),
+            overflow: TrackedMutex::new(VecDeque::with_capacity(config.overflow_capacity)),
+            locations: TrackedMutex::new(HashMap::with_capacity(orders)),
+            pickup_freshness: Mutex::new(Vec::with_capacity(orders)),
+            placement_latency: Mutex::new(Vec::with_capacity(orders)),
+            slot_generation: Mutex::new(0),
+            slot_freed: Condvar::new(),
+            actions: Arc::new(TrackedMutex::new(Vec::with_capacity(
+                orders * EXPECTED_ACTIONS_PER_ORDER,
+            ))),
+            observers: RwLock::new(Vec::new()),
+            clock: MonotonicClock::new(),
+            seq: AtomicU64::new(0),
+            next_group: AtomicU64::new(1),
+            counters: ActionCounters::default(),
+            last_recorded: Mutex::new(HashMap::with_capacity(orders)),
+            outcomes: Mutex::new(HashMap::with_capacity(orders)),
+            config,
+        }
+    }
+
+    /// Rebuilds the kitchen an earlier run left behind at `up_to`, from that run's
+    /// ledger, so placements and pickups can carry on from it. Actions after
+    /// `up_to` are ignored. Orders still stored are put back where the ledger left
+    /// them with the freshness they had, picked-up and discarded orders stay gone,
+    /// and the replayed actions start the new kitchen's ledger.
+    ///
+    /// `actions` must be in ledger order, and the replayed part must pass
+    /// [`validate_actions`]; its errors are returned otherwise.
+    pub fn from_actions(
+        config: KitchenConfig,
+        orders: &[Order],
+        actions: &[Action],
+        up_to: SystemTime,
+    ) -> Result<Self, Vec<ActionError>> {
+        let up_to = up_to.duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+        let replayed = actions.partition_point(|action| action.timestamp <= up_to);
+        let actions = &actions[..replayed];
+        validate_actions(actions, orders, &config)?;
+
+        let kitchen = Self::with_config(config);
+        let orders: HashMap<&str, &Order> = orders
+            .iter()
+            .map(|order| (order.id.as_str(), order))
+            .collect();
+        let mut ledger = Vec::with_capacity(actions.len());
+
+        for action in actions {
+            let timestamp = UNIX_EPOCH + Duration::from_micros(action.timestamp);
+            let location = action.target;
+
+            match action.action {
+                ActionKind::Place => {
+                    let mut stored =
+                        StoredOrder::new(orders[action.id.as_str()].clone(), timestamp);
+                    stored.relocate(&kitchen.config, location, action.unit, timestamp);
+                    kitchen.restore(stored, location);
+                }
+                ActionKind::Move => {
+                    if let Some(mut stored) = kitchen.remove_order(&action.id, &|stored, _| stored)
+                    {
+                        stored.relocate(&kitchen.config, location, action.unit, timestamp);
+                        kitchen.restore(stored, location);
+                    }
+                }
+                ActionKind::Pickup | ActionKind::Discard => {
+                    kitchen
+                        .outcomes
+                        .lock()
+                        .unwrap()
+                        .insert(action.id.clone(), action.action);
+                    if let Some(stored) = kitchen.remove_order(&action.id, &|stored, _| stored) {
+                        kitchen.locations.lock().unwrap().remove(&action.id);
+                        if action.action == ActionKind::Pickup {
+                            let fraction = stored.freshness_fraction(&kitchen.config, timestamp);
+                            kitchen.pickup_freshness.lock().unwrap().push(fraction);
+                        }
+                    }
+                }
+            }
+
+            kitchen
+                .last_recorded
+                .lock()
+                .unwrap()
+                .insert(action.id.clone(), action.timestamp);
+            kitchen.counters.count(action);
+            ledger.push(Action {
+                seq: ledger.len() as u64,
+                ..action.clone()
+            });
+        }
+
+        kitchen
+            .seq
+            .store(ledger.len() as u64, AtomicOrdering::Relaxed);
+        let last_group = ledger.iter().map(|action| action.group).max();
+        kitchen
+            .next_group
+            .store(last_group.unwrap_or(0) + 1, AtomicOrdering::Relaxed);
+        *kitchen.actions.lock().unwrap() = ledger;
+        Ok(kitchen)
+    }
+
+    // puts a replayed order at `location` without recording anything
+    fn restore(&self, stored: StoredOrder, location: Location) {
+        let order_id = stored.order.id.clone();
+        if location == Location::Shelf {
+            let entry = OrderEntry {
+                order_id: order_id.clone(),
+                expires_at: self.calculate_expiration(&stored),
+            };
+            self.shelf.write().unwrap().insert(order_id.clone(), stored);
+            self.shelf_queue.lock().unwrap().push(Reverse(entry));
+        } else {
+            self.storage(location).lock().unwrap().push_back(stored);
+        }
+        self.set_location(&order_id, location);
+    }
+
+    pub fn config(&self) -> &KitchenConfig {
+        &self.config
+    }
+
+    /// Registers a callback invoked with every action as it is recorded.
+    ///
+    /// Callbacks run on the thread that recorded the action, after it has been
+    /// buffered for the ledger and without the actions lock held. They may still
+    /// run while a storage lock is held, so they must not call back into the kitchen.
+    ///
+    /// Every action recorded by one placement, pickup, cancellation or move
+    /// carries the same `group`, so a discard or move can be traced back to the
+    /// operation that caused it.
+    pub fn on_action(&self, observer: ActionObserver) {
+        self.observers.write().unwrap().push(observer);
+    }
+
+    /// Lock acquisition counts and accumulated wait time for each of the kitchen's locks.
+    #[cfg(feature = "metrics")]
+    pub fn lock_stats(&self) -> LockStats {
+        LockStats {
+            cooler: self.cooler.stat(),
+            heater: self.heater.stat(),
+            shelf: self.shelf.stat(),
+            shelf_queue: self.shelf_queue.stat(),
+            overflow: self.overflow.stat(),
+            locations: self.locations.stat(),
+            actions: self.actions.stat(),
+        }
+    }
+
+    /// Clears all stored orders and recorded actions so one kitchen can be reused
+    /// across test scenarios.
+    ///
+    /// Must not be called while other threads are placing or picking up orders:
+    /// an in-flight operation may observe a half-cleared kitchen, and actions
+    /// still buffered on a live worker thread would land in the cleared ledger.
+    #[cfg(test)]
+    pub fn reset(&self) {
+        ACTION_BUFFERS.with(|buffers| buffers.borrow_mut().discard(&self.actions));
+
+        let mut containers = self.lock_containers(&Location::ALL);
+        let mut queue = self.shelf_queue.lock().unwrap();
+        let mut locations = self.locations.lock().unwrap();
+        let mut actions = self.actions.lock().unwrap();
+
+        for location in [Location::Cooler, Location::Heater, Location::Overflow] {
+            containers.storage(location).clear();
+        }
+        containers.shelf().clear();
+        queue.clear();
+        locations.clear();
+        actions.clear();
+        self.pickup_freshness.lock().unwrap().clear();
+        self.placement_latency.lock().unwrap().clear();
+        self.clock.reset();
+        self.seq.store(0, AtomicOrdering::Relaxed);
+        self.next_group.store(1, AtomicOrdering::Relaxed);
+        self.counters.clear();
+        self.last_recorded.lock().unwrap().clear();
+        self.outcomes.lock().unwrap().clear();
+    }
+
+    // Starts a group for the actions recorded on this thread until the guard
+    // drops, so a placement or pickup and the moves and discards it causes share
+    // one Action::group. A nested operation joins the group already running.
+    fn begin_operation(&self) -> OperationGuard {
+        let previous = OPERATION_GROUP.with(Cell::get);
+        if previous == 0 {
+            let group = self.next_group.fetch_add(1, AtomicOrdering::Relaxed);
+            OPERATION_GROUP.with(|current| current.set(group));
+        }
+        OperationGuard { previous }
+    }
+
+    fn record_action(
+        &self,
+        order_id: String,
+        action_type: ActionKind,
+        target: Unit,
+        timestamp: SystemTime,
+    ) {
+        self.record(order_id, action_type, target, timestamp, None);
+    }
+
+    fn record_discard(
+        &self,
+        order_id: String,
+        target: Unit,
+        timestamp: SystemTime,
+        reason: DiscardReason,
+    ) {
+        self.record(
+            order_id,
+            ActionKind::Discard,
+            target,
+            timestamp,
+            Some(reason),
+        );
+    }
+
+    fn record(
+        &self,
+        order_id: String,
+        action_type: ActionKind,
+        target: Unit,
+        timestamp: SystemTime,
+        reason: Option<DiscardReason>,
+    ) {
+        let provided_timestamp_micros =
+            timestamp.duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+
+        // Threads take their timestamps before locking, so a pickup can carry an earlier
+        // time than the place it follows. Keep each order's own actions strictly after
+        // one another; the global clock below only orders them by when they're recorded.
+        let provided_timestamp_micros = {
+            let mut last_recorded = self.last_recorded.lock().unwrap();
+            let last = last_recorded.entry(order_id.clone()).or_default();
+            let mut clamped = provided_timestamp_micros.max(last.saturating_add(1));
+            if self.config.deterministic {
+                // without the global clock, this keeps a discard that makes room
+                // ahead of the placement that needed it once the ledger is sorted
+                clamped = OPERATION_CLOCK.with(|clock| {
+                    let next = clamped.max(clock.get().saturating_add(1));
+                    clock.set(next);
+                    next
+                });
+            }
+            *last = clamped;
+            clamped
+        };
+
+        // need to ensure monotonicity across threads; deterministic runs do it in drain_actions
+        let monotonic_timestamp_micros = if self.config.deterministic {
+            provided_timestamp_micros
+        } else {
+            self.clock.next(provided_timestamp_micros)
+        };
+
+        let monotonic_timestamp =
+            UNIX_EPOCH + std::time::Duration::from_micros(monotonic_timestamp_micros);
+
+        let mut action = Action::new(&order_id, action_type, target.location, monotonic_timestamp);
+        action.unit = target.index;
+        action.seq = self.seq.fetch_add(1, AtomicOrdering::Relaxed);
+        action.reason = reason;
+        action.group = OPERATION_GROUP.with(Cell::get);
+        self.counters.count(&action);
+        if matches!(action_type, ActionKind::Pickup | ActionKind::Discard) {
+            self.outcomes
+                .lock()
+                .unwrap()
+                .insert(order_id.clone(), action_type);
+        }
+        ACTION_BUFFERS.with(|buffers| buffers.borrow_mut().push(&self.actions, action.clone()));
+        match reason {
+            Some(reason) => info!(
+                "[{}] {}: {} -> {} ({})",
+                monotonic_timestamp_micros, action_type, order_id, target, reason
+            ),
+            None => info!(
+                "[{}] {}: {} -> {}",
+                monotonic_timestamp_micros, action_type, order_id, target
+            ),
+        }
+
+        for observer in self.observers.read().unwrap().iter() {
+            observer(&action);
+        }
+    }
+
+    /// Stores `order`, making room according to the configured [`OverflowPolicy`]
+    /// if its ideal storage and the shelf are full.
+    // the binary's schedulers always know when a placement was due
+    #[allow(dead_code)]
+    pub fn place_order(&self, order: Order, timestamp: SystemTime) -> Placement {
+        self.place_order_scheduled(order, None, timestamp)
+    }
+
+    /// Like [`Kitchen::place_order`], but with `scheduled` also notes how long
+    /// after it the placement ran, for [`Kitchen::placement_latency_stats`].
+    pub fn place_order_scheduled(
+        &self,
+        order: Order,
+        scheduled: Option<SystemTime>,
+        timestamp: SystemTime,
+    ) -> Placement {
+        if let Some(scheduled) = scheduled {
+            let latency = timestamp.duration_since(scheduled).unwrap_or_default();
+            self.placement_latency.lock().unwrap().push(latency);
+        }
+        let _operation = self.begin_operation();
+
+        // a second order with a resident id would clobber the first in the shelf map
+        // and leave a stale queue entry behind, so refuse it outright
+        match self.locations.lock().unwrap().entry(order.id.clone()) {
+            Entry::Occupied(_) => {
+                info!(
+                    "order {} is already in the kitchen, ignoring duplicate",
+                    order.id
+                );
+                return Placement::Ignored;
+            }
+            Entry::Vacant(slot) => {
+                // a reused id starts afresh
+                self.outcomes.lock().unwrap().remove(slot.key());
+                slot.insert(None);
+            }
+        }
+
+        if let Some(proactive) = self.config.proactive_rebalance {
+            self.relieve_shelf(proactive.high_water_mark, timestamp);
+        }
+
+        let stored = StoredOrder::new(order.clone(), timestamp);
+
+        let ideal_target = match stored.temp {
+            Temp::Hot => Location::Heater,
+            Temp::Cold => Location::Cooler,
+            Temp::Room | Temp::Any => self.roomiest_location(stored.temp),
+        };
+
+        if self.try_place(&stored, ideal_target, timestamp) {
+            return Placement::Placed;
+        }
+
+        match self.config.overflow {
+            OverflowPolicy::ForceDiscard => self.force_place(&stored, ideal_target, timestamp),
+            OverflowPolicy::Reject => self.reject(&order.id),
+            OverflowPolicy::WaitForSlot { timeout, fallback } => {
+                // waiting any longer than the order stays fresh is pointless
+                let timeout = timeout.unwrap_or_else(|| self.time_to_expiry(&stored));
+                self.wait_for_slot(stored, ideal_target, timestamp, timeout, fallback)
+            }
+        }
+    }
+
+    // ideal storage first, then the shelf, then the overflow rack
+    fn try_place(
+        &self,
+        stored: &StoredOrder,
+        ideal_target: Location,
+        timestamp: SystemTime,
+    ) -> bool {
+        if ideal_target != Location::Shelf
+            && self.try_place_in_storage(stored, ideal_target, timestamp)
+        {
+            return true;
+        }
+        if self.try_place_on_shelf(stored, timestamp) {
+            return true;
+        }
+        self.config.overflow_capacity > 0
+            && self.try_place_in_storage(stored, Location::Overflow, timestamp)
+    }
+
+    // everything is full: move or discard a resident order to make room
+    fn force_place(
+        &self,
+        stored: &StoredOrder,
+        ideal_target: Location,
+        timestamp: SystemTime,
+    ) -> Placement {
+        let moved = (stored.temp == Temp::Hot || stored.temp == Temp::Cold)
+            && self.try_move_to_shelf_from_storage(stored, ideal_target, timestamp);
+        if moved {
+            return Placement::Placed;
+        }
+        self.place_on_shelf_discarding(stored, timestamp)
+    }
+
+    // Discards from the shelf until the order fits, or rejects it if it's the
+    // cheapest order to lose. Pickups run concurrently, so the shelf may have
+    // room again, or even be empty, by the time this runs.
+    fn place_on_shelf_discarding(&self, stored: &StoredOrder, timestamp: SystemTime) -> Placement {
+        while !self.try_place_on_shelf(stored, timestamp) {
+            if self.discard_from_shelf(stored, timestamp) == Eviction::Declined {
+                return self.reject(&stored.order.id);
+            }
+        }
+        Placement::Placed
+    }
+
+    fn reject(&self, order_id: &str) -> Placement {
+        self.release(order_id);
+        info!("rejected order {order_id}: no free slot");
+        Placement::Rejected
+    }
+
+    // Retries placement each time a slot frees up. The order is placed at the
+    // time it actually found room, i.e. `timestamp` plus however long it waited.
+    fn wait_for_slot(
+        &self,
+        mut stored: StoredOrder,
+        ideal_target: Location,
+        timestamp: SystemTime,
+        timeout: Duration,
+        fallback: WaitFallback,
+    ) -> Placement {
+        let started = Instant::now();
+        loop {
+            // read the generation before retrying so a slot freed mid-attempt isn't missed
+            let seen = *self.slot_generation.lock().unwrap();
+
+            let now = timestamp + started.elapsed();
+            stored.placed_at = now;
+            stored.snapshot_at = now;
+            if self.try_place(&stored, ideal_target, now) {
+                return Placement::Placed;
+            }
+
+            let Some(remaining) = timeout.checked_sub(started.elapsed()) else {
+                return match fallback {
+                    WaitFallback::Reject => self.reject(&stored.order.id),
+                    WaitFallback::ForceDiscard => self.force_place(&stored, ideal_target, now),
+                };
+            };
+            let generation = self.slot_generation.lock().unwrap();
+            if *generation == seen {
+                let _ = self.slot_freed.wait_timeout(generation, remaining).unwrap();
+            }
+        }
+    }
+
+    /// Free slots left at `location`.
+    pub fn remaining_capacity(&self, location: Location) -> usize {
+        self.capacity(location)
+            .saturating_sub(self.occupancy(location))
+    }
+
+    /// Orders currently stored at `location`.
+    pub fn occupancy(&self, location: Location) -> usize {
+        match location {
+            Location::Shelf => self.shelf.read().unwrap().len(),
+            Location::Cooler | Location::Heater | Location::Overflow => {
+                self.storage(location).lock().unwrap().len()
+            }
+        }
+    }
+
+    // Of the locations where `temp` degrades at the ideal rate, the one with the
+    // largest fraction of its capacity free, preferring the shelf on ties. Only
+    // the shelf if none is ideal.
+    fn roomiest_location(&self, temp: Temp) -> Location {
+        let ideal_rate = self.config.degradation.ideal_rate();
+        let mut best = (Location::Shelf, -1.0);
+        // one lock at a time, in cooler/heater/shelf order
+        for location in [Location::Cooler, Location::Heater, Location::Shelf] {
+            if self.config.rate(temp, location) != ideal_rate {
+                continue;
+            }
+            let free = self.remaining_capacity(location) as f64 / self.capacity(location) as f64;
+            if free > best.1 || (free == best.1 && location == Location::Shelf) {
+                best = (location, free);
+            }
+        }
+        best.0
+    }
+
+    // the queue of a location other than the shelf
+    fn storage(&self, location: Location) -> &TrackedMutex<VecDeque<StoredOrder>> {
+        match location {
+            Location::Cooler => &self.cooler,
+            Location::Heater => &self.heater,
+            Location::Overflow => &self.overflow,
+            Location::Shelf => panic!("the shelf is not a queue"),
+        }
+    }
+
+    // Locks the containers of `locations`, in the kitchen's lock order whatever
+    // order they are listed in.
+    fn lock_containers(&self, locations: &[Location]) -> Containers<'_> {
+        let wanted = |location| locations.contains(&location);
+        let cooler = wanted(Location::Cooler).then(|| self.cooler.lock().unwrap());
+        let heater = wanted(Location::Heater).then(|| self.heater.lock().unwrap());
+        let shelf = wanted(Location::Shelf).then(|| self.shelf.write().unwrap());
+        let overflow = wanted(Location::Overflow).then(|| self.overflow.lock().unwrap());
+        Containers {
+            cooler,
+            heater,
+            shelf,
+            overflow,
+        }
+    }
+
+    fn capacity(&self, location: Location) -> usize {
+        self.config.capacity(location)
+    }
+
+    // First unit of `location` with room, given the unit of each order stored
+    // there. The caller has checked that the location as a whole has room.
+    fn free_unit(&self, location: Location, occupied: impl Iterator<Item = usize>) -> usize {
+        let mut counts = vec![0; self.config.units(location)];
+        for unit in occupied {
+            counts[unit] += 1;
+        }
+        let capacity = self.config.unit_capacity(location);
+        counts
+            .iter()
+            .position(|&count| count < capacity)
+            .unwrap_or(0)
+    }
+
+    // drops a removed shelf order's queue entry; called with the shelf lock held
+    fn unqueue(&self, order_id: &str) {
+        self.shelf_queue
+            .lock()
+            .unwrap()
+            .retain(|Reverse(entry)| entry.order_id != order_id);
+    }
+
+    fn try_place_in_storage(
+        &self,
+        stored: &StoredOrder,
+        target: Location,
+        timestamp: SystemTime,
+    ) -> bool {
+        let mut storage = self.storage(target).lock().unwrap();
+
+        let capacity = self.capacity(target);
+        if storage.len() >= capacity {
+            // expiry is otherwise only noticed at pickup; reclaim dead orders' slots first
+            self.discard_expired(&mut storage, timestamp);
+            if storage.len() >= capacity {
+                return false;
+            }
+        }
+
+        self.push_to_storage(&mut storage, stored, target, timestamp);
+        true
+    }
+
+    // Stores a new order at `target`, which is a location other than the shelf
+    // whose lock the caller holds and which has room.
+    fn push_to_storage(
+        &self,
+        storage: &mut VecDeque<StoredOrder>,
+        stored: &StoredOrder,
+        target: Location,
+        timestamp: SystemTime,
+    ) {
+        let unit = self.free_unit(target, storage.iter().map(|stored| stored.unit));
+        let mut stored = stored.clone();
+        stored.relocate(&self.config, target, unit, timestamp);
+        let order_id = stored.order.id.clone();
+        let target = stored.target();
+        storage.push_back(stored);
+        self.set_location(&order_id, target.location);
+        self.record_action(order_id, ActionKind::Place, target, timestamp);
+    }
+
+    // Discards every expired order in `storage`. The caller holds the storage
+    // lock, so a concurrent pickup either already took the order or won't find it.
+    fn discard_expired(&self, storage: &mut VecDeque<StoredOrder>, now: SystemTime) {
+        let config = &self.config;
+        let mut idx = 0;
+        while idx < storage.len() {
+            if !storage[idx].is_expired(config, now) {
+                idx += 1;
+                continue;
+            }
+            let stored = storage.remove(idx).unwrap();
+            self.release(&stored.order.id);
+            let (target, reason) = (stored.target(), stored.expiry_reason());
+            self.record_discard(stored.order.id, target, now, reason);
+        }
+    }
+
+    fn try_place_on_shelf(&self, stored: &StoredOrder, timestamp: SystemTime) -> bool {
+        let mut shelf = self.shelf.write().unwrap();
+        if shelf.len() >= self.capacity(Location::Shelf) {
+            return false;
+        }
+
+        let unit = self.free_unit(Location::Shelf, shelf.values().map(|stored| stored.unit));
+        let mut stored = stored.clone();
+        stored.relocate(&self.config, Location::Shelf, unit, timestamp);
+
+        let order_id = stored.order.id.clone();
+        let target = stored.target();
+        let expires_at = self.calculate_expiration(&stored);
+        let entry = OrderEntry {
+            order_id: order_id.clone(),
+            expires_at,
+        };
+
+        shelf.insert(order_id.clone(), stored);
+
+        self.shelf_queue.lock().unwrap().push(Reverse(entry));
+        self.set_location(&order_id, Location::Shelf);
+        // record before unlocking so a pickup can't be recorded ahead of this
+        self.record_action(order_id, ActionKind::Place, target, timestamp);
+        true
+    }
+
+    // Places `incoming` in `source`, making room by moving the oldest order
+    // there to the shelf, evicting from the shelf first if it's full. Both stay
+    // locked until `incoming` is in, so nothing can take the shelf slot or the
+    // freed one in between. Declines if the move isn't worth its penalty, or if
+    // the moved order would be the cheapest on the shelf to lose, leaving
+    // `incoming` for the shelf.
+    fn try_move_to_shelf_from_storage(
+        &self,
+        incoming: &StoredOrder,
+        source: Location,
+        timestamp: SystemTime,
+    ) -> bool {
+        let mut containers = self.lock_containers(&[source, Location::Shelf]);
+        // a pickup may have freed a slot since the placement found it full
+        if containers.len(source) < self.capacity(source) {
+            self.push_to_storage(containers.storage(source), incoming, source, timestamp);
+            return true;
+        }
+        let Some(oldest) = containers.storage(source).front() else {
+            return false;
+        };
+        // one order trades its ideal slot for the shelf, the other the reverse
+        let gain = incoming.move_gain(&self.config, Location::Shelf, source, timestamp)
+            + oldest.move_gain(&self.config, source, Location::Shelf, timestamp);
+        if !self.worth_moving(gain) {
+            return false;
+        }
+        let mut moved = containers.storage(source).pop_front().unwrap();
+        if containers.len(Location::Shelf) >= self.capacity(Location::Shelf)
+            && self.evict_locked(containers.shelf(), &moved, timestamp) == Eviction::Declined
+        {
+            containers.storage(source).push_front(moved);
+            return false;
+        }
+
+        let shelf_units = containers.shelf().values().map(|stored| stored.unit);
+        let unit = self.free_unit(Location::Shelf, shelf_units);
+        moved.relocate(&self.config, Location::Shelf, unit, timestamp);
+        let order_id = moved.order.id.clone();
+        let target = moved.target();
+        let entry = OrderEntry {
+            order_id: order_id.clone(),
+            expires_at: self.calculate_expiration(&moved),
+        };
+        containers.shelf().insert(order_id.clone(), moved);
+        self.shelf_queue.lock().unwrap().push(Reverse(entry));
+        self.set_location(&order_id, Location::Shelf);
+        // record before unlocking so a pickup can't be recorded ahead of this
+        self.record_action(order_id, ActionKind::Move, target, timestamp);
+        self.push_to_storage(containers.storage(source), incoming, source, timestamp);
+        true
+    }
+
+    // Makes room on the shelf for `incoming`. Finding the shelf already empty
+    // is legitimate, since pickups run concurrently.
+    fn discard_from_shelf(&self, incoming: &StoredOrder, timestamp: SystemTime) -> Eviction {
+        let eviction = self.evict_locked(&mut self.shelf.write().unwrap(), incoming, timestamp);
+        if eviction == Eviction::Evicted {
+            self.rebalance(timestamp);
+        }
+        eviction
+    }
+
+    // Discards one order from the shelf, whose write lock the caller holds, to
+    // make room for `incoming`. Under MinRevenueLoss, declines instead if
+    // `incoming` is worth less than every order on the shelf.
+    fn evict_locked(
+        &self,
+        shelf: &mut HashMap<String, StoredOrder>,
+        incoming: &StoredOrder,
+        timestamp: SystemTime,
+    ) -> Eviction {
+        let mut queue = self.shelf_queue.lock().unwrap();
+
+        if self.config.eviction == EvictionStrategy::MinRevenueLoss {
+            let config = &self.config;
+            let victim = shelf
+                .values()
+                .map(|stored| (stored.expected_value(config, timestamp), stored))
+                .min_by(|(a, _), (b, _)| a.total_cmp(b))
+                .map(|(value, stored)| (value, stored.order.id.clone()));
+
+            if let Some((value, order_id)) = victim {
+                if incoming.expected_value(config, timestamp) < value {
+                    return Eviction::Declined;
+                }
+                let stored = shelf.remove(&order_id).unwrap();
+                queue.retain(|Reverse(entry)| entry.order_id != order_id);
+                self.release(&order_id);
+                let reason = self.eviction_reason(&stored, timestamp);
+                self.record_discard(order_id, stored.target(), timestamp, reason);
+                return Eviction::Evicted;
+            }
+        }
+
+        while let Some(Reverse(entry)) = queue.pop() {
+            if let Some(stored) = shelf.remove(&entry.order_id) {
+                self.release(&entry.order_id);
+                let reason = self.eviction_reason(&stored, timestamp);
+                self.record_discard(entry.order_id, stored.target(), timestamp, reason);
+                return Eviction::Evicted;
+            }
+        }
+        // every shelf order is queued, so an empty queue means an empty shelf
+        Eviction::Empty
+    }
+
+    // an evicted order that had already expired would have been discarded anyway
+    fn eviction_reason(&self, stored: &StoredOrder, now: SystemTime) -> DiscardReason {
+        if stored.is_expired(&self.config, now) {
+            stored.expiry_reason()
+        } else {
+            DiscardReason::Capacity
+        }
+    }
+
+    /// Moves the most valuable hot or cold order off the shelf into its ideal
+    /// storage if a slot there is free. At most one move per call.
+    fn rebalance(&self, now: SystemTime) -> bool {
+        let mut containers =
+            self.lock_containers(&[Location::Cooler, Location::Heater, Location::Shelf]);
+        let cooler_free = containers.len(Location::Cooler) < self.capacity(Location::Cooler);
+        let heater_free = containers.len(Location::Heater) < self.capacity(Location::Heater);
+        if !cooler_free && !heater_free {
+            return false;
+        }
+
+        let config = &self.config;
+        // only hot and cold orders get this far
+        let storage_for = |temp| {
+            if temp == Temp::Hot {
+                Location::Heater
+            } else {
+                Location::Cooler
+            }
+        };
+        let candidate = containers
+            .shelf()
+            .values()
+            .filter(|stored| match stored.temp {
+                Temp::Hot => heater_free,
+                Temp::Cold => cooler_free,
+                Temp::Room | Temp::Any => false,
+            })
+            .filter(|stored| !stored.is_expired(config, now))
+            .filter(|stored| {
+                let gain = stored.move_gain(config, Location::Shelf, storage_for(stored.temp), now);
+                self.worth_moving(gain)
+            })
+            .max_by(|a, b| {
+                a.expected_value(config, now)
+                    .total_cmp(&b.expected_value(config, now))
+            })
+            .map(|stored| stored.order.id.clone());
+
+        let Some(order_id) = candidate else {
+            return false;
+        };
+
+        let mut moved = containers.shelf().remove(&order_id).unwrap();
+        self.unqueue(&order_id);
+
+        let target = storage_for(moved.temp);
+        let unit = self.free_unit(target, containers.storage(target).iter().map(|o| o.unit));
+        moved.relocate(config, target, unit, now);
+        let target = moved.target();
+        containers.storage(target.location).push_back(moved);
+        self.set_location(&order_id, target.location);
+        self.record_action(order_id, ActionKind::Move, target, now);
+        self.notify_slot_freed();
+        true
+    }
+
+    // Whether a move saving `gain` is worth the configured move penalty.
+    fn worth_moving(&self, gain: f64) -> bool {
+        self.config
+            .move_penalty
+            .is_none_or(|penalty| gain > penalty)
+    }
+
+    // Rebalances until the shelf is below `high_water_mark` or nothing more can
+    // move. Pickups already rebalance into the slots they free, so what is left
+    // to reclaim is mostly expired orders still holding cooler and heater slots.
+    fn relieve_shelf(&self, high_water_mark: f64, now: SystemTime) {
+        let mark = high_water_mark * self.capacity(Location::Shelf) as f64;
+        if (self.occupancy(Location::Shelf) as f64) < mark {
+            return;
+        }
+        for location in [Location::Cooler, Location::Heater] {
+            let mut storage = self.storage(location).lock().unwrap();
+            self.discard_expired(&mut storage, now);
+        }
+        while self.occupancy(Location::Shelf) as f64 >= mark && self.rebalance(now) {}
+    }
+
+    /// Moves an order from wherever it is stored to `to`, recording a `move`.
+    /// Returns false, doing nothing, if the order isn't in the kitchen, is
+    /// already at `to`, or `to` is full.
+    pub fn move_order(&self, order_id: &str, to: Location, timestamp: SystemTime) -> bool {
+        let _operation = self.begin_operation();
+        let Some(from) = self.where_is(order_id) else {
+            return false;
+        };
+        if from == to {
+            return false;
+        }
+
+        let mut containers = self.lock_containers(&[from, to]);
+        if containers.len(to) >= self.capacity(to) {
+            return false;
+        }
+
+        // the order may have been picked up since where_is looked
+        let removed = if from == Location::Shelf {
+            containers.shelf().remove(order_id)
+        } else {
+            let storage = containers.storage(from);
+            let pos = storage.iter().position(|o| o.order.id == order_id);
+            pos.and_then(|pos| storage.remove(pos))
+        };
+        let Some(mut moved) = removed else {
+            return false;
+        };
+        if from == Location::Shelf {
+            self.unqueue(order_id);
+        }
+
+        let unit = if to == Location::Shelf {
+            self.free_unit(to, containers.shelf().values().map(|o| o.unit))
+        } else {
+            self.free_unit(to, containers.storage(to).iter().map(|o| o.unit))
+        };
+        moved.relocate(&self.config, to, unit, timestamp);
+        let target = moved.target();
+        if to == Location::Shelf {
+            let entry = OrderEntry {
+                order_id: order_id.to_string(),
+                expires_at: self.calculate_expiration(&moved),
+            };
+            containers.shelf().insert(order_id.to_string(), moved);
+            self.shelf_queue.lock().unwrap().push(Reverse(entry));
+        } else {
+            containers.storage(to).push_back(moved);
+        }
+        self.set_location(order_id, to);
+        self.record_action(order_id.to_string(), ActionKind::Move, target, timestamp);
+        self.notify_slot_freed();
+        true
+    }
+
+    // Moves orders the cooler, heater or overflow rack is holding at a non-ideal
+    // temperature into a cooler or heater that is ideal for them and has room.
+    fn restore_ideal(&self, now: SystemTime) {
+        let ideal_rate = self.config.degradation.ideal_rate();
+        let mut misplaced = Vec::new();
+        for location in [Location::Cooler, Location::Heater, Location::Overflow] {
+            let storage = self.storage(location).lock().unwrap();
+            misplaced.extend(
+                storage
+                    .iter()
+                    .filter(|stored| self.config.rate(stored.temp, location) != ideal_rate)
+                    .filter(|stored| !stored.is_expired(&self.config, now))
+                    .map(|stored| (stored.order.id.clone(), stored.temp)),
+            );
+        }
+
+        for (order_id, temp) in misplaced {
+            let target = [Location::Cooler, Location::Heater]
+                .into_iter()
+                .find(|&location| {
+                    self.config.rate(temp, location) == ideal_rate
+                        && self.remaining_capacity(location) > 0
+                });
+            if let Some(target) = target {
+                self.move_order(&order_id, target, now);
+            }
+        }
+    }
+
+    // Moves the most valuable unexpired orders from the overflow rack back onto
+    // the shelf while it has room.
+    fn refill_shelf(&self, now: SystemTime) {
+        if self.config.overflow_capacity == 0 {
+            return;
+        }
+
+        let mut containers = self.lock_containers(&[Location::Shelf, Location::Overflow]);
+        let config = &self.config;
+        while containers.len(Location::Shelf) < self.capacity(Location::Shelf) {
+            let candidate = containers
+                .storage(Location::Overflow)
+                .iter()
+                .enumerate()
+                .filter(|(_, stored)| !stored.is_expired(config, now))
+                .max_by(|(_, a), (_, b)| {
+                    a.expected_value(config, now)
+                        .total_cmp(&b.expected_value(config, now))
+                })
+                .map(|(idx, _)| idx);
+            let Some(idx) = candidate else {
+                return;
+            };
+
+            let mut moved = containers.storage(Location::Overflow).remove(idx).unwrap();
+            let shelf_units = containers.shelf().values().map(|stored| stored.unit);
+            let unit = self.free_unit(Location::Shelf, shelf_units);
+            moved.relocate(config, Location::Shelf, unit, now);
+            let order_id = moved.order.id.clone();
+            let target = moved.target();
+            let entry = OrderEntry {
+                order_id: order_id.clone(),
+                expires_at: self.calculate_expiration(&moved),
+            };
+            containers.shelf().insert(order_id.clone(), moved);
+            self.shelf_queue.lock().unwrap().push(Reverse(entry));
+            self.set_location(&order_id, Location::Shelf);
+            self.record_action(order_id, ActionKind::Move, target, now);
+            self.notify_slot_freed();
+        }
+    }
+
+    // called with the destination's lock held so the index never lags the containers
+    fn set_location(&self, order_id: &str, location: Location) {
+        self.locations
+            .lock()
+            .unwrap()
+            .insert(order_id.to_string(), Some(location));
+    }
+
+    // the order has left the kitchen; its id may be placed again
+    fn release(&self, order_id: &str) {
+        self.locations.lock().unwrap().remove(order_id);
+        self.notify_slot_freed();
+    }
+
+    // wakes placements waiting under OverflowPolicy::WaitForSlot
+    fn notify_slot_freed(&self) {
+        *self.slot_generation.lock().unwrap() += 1;
+        self.slot_freed.notify_all();
+    }
+
+    // how long `stored` has left from its last snapshot where it is now
+    fn time_to_expiry(&self, stored: &StoredOrder) -> Duration {
+        let degradation_rate = stored.rate(&self.config);
+        self.config
+            .curve
+            .time_to_expiry(stored.freshness_snapshot, degradation_rate as f64)
+    }
+
+    fn calculate_expiration(&self, stored: &StoredOrder) -> i64 {
+        let until_expiration = self.time_to_expiry(stored);
+
+        let snapshot_micros = stored
+            .snapshot_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as i64;
+        snapshot_micros.saturating_add(until_expiration.as_micros().min(i64::MAX as u128) as i64)
+    }
+
+    /// Where `order_id` is stored, or None if it isn't in the kitchen or is
+    /// still being placed.
+    pub fn where_is(&self, order_id: &str) -> Option<Location> {
+        self.locations
+            .lock()
+            .unwrap()
+            .get(order_id)
+            .copied()
+            .flatten()
+    }
+
+    /// Checks the kitchen's internal consistency: no unit over capacity, no
+    /// order stored twice, the shelf and its eviction queue holding the same
+    /// orders, and the location index matching where orders actually are. Takes
+    /// every container lock at once, so it sees a consistent state even while
+    /// other threads are working.
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantError>> {
+        let mut containers = self.lock_containers(&Location::ALL);
+        let queue = self.shelf_queue.lock().unwrap();
+        let locations = self.locations.lock().unwrap();
+        let mut errors = Vec::new();
+
+        let mut actual: HashMap<&str, Location> = HashMap::new();
+        let mut occupancy: HashMap<Unit, usize> = HashMap::new();
+        let shelf = containers.shelf.take().unwrap();
+        let storages = [
+            (Location::Cooler, containers.cooler.take().unwrap()),
+            (Location::Heater, containers.heater.take().unwrap()),
+            (Location::Overflow, containers.overflow.take().unwrap()),
+        ];
+        let stored =
+            shelf
+                .values()
+                .map(|stored| (Location::Shelf, stored))
+                .chain(storages.iter().flat_map(|(location, storage)| {
+                    storage.iter().map(|stored| (*location, stored))
+                }));
+        for (location, stored) in stored {
+            let id = stored.order.id.as_str();
+            if actual.insert(id, location).is_some() {
+                errors.push(InvariantError::StoredTwice { id: id.to_string() });
+            }
+            let unit = Unit {
+                location,
+                index: stored.unit,
+            };
+            *occupancy.entry(unit).or_default() += 1;
+        }
+        for (unit, len) in occupancy {
+            let capacity = if unit.index < self.config.units(unit.location) {
+                self.config.unit_capacity(unit.location)
+            } else {
+                0
+            };
+            if len > capacity {
+                errors.push(InvariantError::OverCapacity {
+                    unit,
+                    len,
+                    capacity,
+                });
+            }
+        }
+
+        let queued: HashSet<&str> = queue
+            .iter()
+            .map(|Reverse(entry)| entry.order_id.as_str())
+            .collect();
+        for id in shelf.keys().filter(|id| !queued.contains(id.as_str())) {
+            errors.push(InvariantError::Unqueued { id: id.clone() });
+        }
+        for &id in queued.iter().filter(|&&id| !shelf.contains_key(id)) {
+            errors.push(InvariantError::StaleQueueEntry { id: id.to_string() });
+        }
+
+        // an index entry of None is an order still being placed, not yet stored anywhere
+        let indexed = locations
+            .iter()
+            .map(|(id, &indexed)| (id.as_str(), indexed));
+        for (id, indexed) in indexed {
+            if indexed != actual.get(id).copied() {
+                errors.push(InvariantError::WrongIndex {
+                    id: id.to_string(),
+                    indexed,
+                    actual: actual.get(id).copied(),
+                });
+            }
+        }
+        for (&id, &location) in actual
+            .iter()
+            .filter(|(id, _)| !locations.contains_key(**id))
+        {
+            errors.push(InvariantError::WrongIndex {
+                id: id.to_string(),
+                indexed: None,
+                actual: Some(location),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Degradation state of `order_id` as of `now`, or None if it isn't stored.
+    /// Only the container holding the order is locked, and only to copy it out.
+    pub fn inspect(&self, order_id: &str, now: SystemTime) -> Option<OrderInspection> {
+        let (stored, location) = self.find_stored(order_id)?;
+        let config = &self.config;
+        let ideal_rate = config.degradation.ideal_rate();
+        let degrading_ideally = config.rate(stored.temp, location) == ideal_rate;
+        let ideal_location = if degrading_ideally {
+            Some(location)
+        } else {
+            [Location::Cooler, Location::Heater, Location::Shelf]
+                .into_iter()
+                .find(|&candidate| config.rate(stored.temp, candidate) == ideal_rate)
+        };
+        let expires_at =
+            UNIX_EPOCH + Duration::from_micros(self.calculate_expiration(&stored).max(0) as u64);
+
+        Some(OrderInspection {
+            location,
+            ideal_location,
+            degrading_ideally,
+            ever_non_ideal: stored.ever_non_ideal,
+            remaining_freshness: stored.remaining_freshness(config, now),
+            expires_at,
+            order: stored.order,
+        })
+    }
+
+    // a copy of a stored order and where it is, looking where the index says first
+    fn find_stored(&self, order_id: &str) -> Option<(StoredOrder, Location)> {
+        let indexed = self.where_is(order_id);
+        indexed
+            .into_iter()
+            .chain(Location::ALL.into_iter().filter(|&l| Some(l) != indexed))
+            .find_map(|location| {
+                let stored = match location {
+                    Location::Shelf => self.shelf.read().unwrap().get(order_id).cloned(),
+                    Location::Cooler | Location::Heater | Location::Overflow => self
+                        .storage(location)
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .find(|stored| stored.order.id == order_id)
+                        .cloned(),
+                };
+                stored.map(|stored| (stored, location))
+            })
+    }
+
+    /// Calls `f(order_id, location, remaining_freshness)` for every stored order,
+    /// with freshness in seconds as of `now`.
+    ///
+    /// Each container is locked only while it is visited, so this is not a
+    /// consistent snapshot: an order moved concurrently may be seen twice or not
+    /// at all. `f` runs under the container's lock and must not call back into
+    /// the kitchen.
+    pub fn for_each_stored(&self, now: SystemTime, mut f: impl FnMut(&str, Location, f64)) {
+        let config = &self.config;
+        for location in [Location::Cooler, Location::Heater] {
+            for stored in self.storage(location).lock().unwrap().iter() {
+                f(
+                    &stored.order.id,
+                    location,
+                    stored.remaining_freshness(config, now),
+                );
+            }
+        }
+        for stored in self.shelf.read().unwrap().values() {
+            f(
+                &stored.order.id,
+                Location::Shelf,
+                stored.remaining_freshness(config, now),
+            );
+        }
+        for stored in self.overflow.lock().unwrap().iter() {
+            f(
+                &stored.order.id,
+                Location::Overflow,
+                stored.remaining_freshness(config, now),
+            );
+        }
+    }
+
+    /// Hands out `order_id`, or discards it if it is no longer fresh enough. If
+    /// the order isn't in the kitchen nothing is recorded, and the outcome says
+    /// whether it already left or was never there.
+    pub fn pickup_order(&self, order_id: &str, timestamp: SystemTime) -> PickupOutcome {
+        let _operation = self.begin_operation();
+        let handed_out = self.remove_order(order_id, &|stored, location| {
+            self.hand_out(stored, location, timestamp)
+        });
+        if let Some(outcome) = handed_out {
+            self.rebalance(timestamp);
+            self.restore_ideal(timestamp);
+            self.refill_shelf(timestamp);
+            return outcome;
+        }
+
+        match self.outcomes.lock().unwrap().get(order_id) {
+            Some(ActionKind::Pickup) => {
+                info!("order {order_id} was already picked up, nothing to pick up");
+                PickupOutcome::AlreadyPickedUp
+            }
+            Some(_) => {
+                info!("order {order_id} was already discarded, nothing to pick up");
+                PickupOutcome::AlreadyDiscarded
+            }
+            None => PickupOutcome::Unknown,
+        }
+    }
+
+    /// Removes an order the customer cancelled, recording a discard. No action
+    /// is taken if the order is not in the kitchen.
+    pub fn cancel_order(&self, order_id: &str, timestamp: SystemTime) {
+        let _operation = self.begin_operation();
+        let cancelled = self.remove_order(order_id, &|stored, _| {
+            self.release(&stored.order.id);
+            let target = stored.target();
+            self.record_discard(stored.order.id, target, timestamp, DiscardReason::Cancelled);
+        });
+        if cancelled.is_some() {
+            self.rebalance(timestamp);
+            self.restore_ideal(timestamp);
+            self.refill_shelf(timestamp);
+        }
+    }
+
+    // Removes the order from whichever container holds it and passes it to `then`
+    // while that container is still locked, so the action `then` records can't
+    // trail a reused slot. None if the order wasn't in the kitchen.
+    fn remove_order<R>(
+        &self,
+        order_id: &str,
+        then: &impl Fn(StoredOrder, Location) -> R,
+    ) -> Option<R> {
+        let indexed = self.where_is(order_id);
+        if let Some(location) = indexed
+            && let Some(result) = self.remove_from(location, order_id, then)
+        {
+            return Some(result);
+        }
+
+        // the index should always be right, but a full search keeps removals correct if not
+        Location::ALL
+            .into_iter()
+            .filter(|&location| Some(location) != indexed)
+            .find_map(|location| self.remove_from(location, order_id, then))
+    }
+
+    fn remove_from<R>(
+        &self,
+        location: Location,
+        order_id: &str,
+        then: &impl Fn(StoredOrder, Location) -> R,
+    ) -> Option<R> {
+        if location == Location::Shelf {
+            // look before taking the write lock so misses only need a read lock
+            if !self.shelf.read().unwrap().contains_key(order_id) {
+                return None;
+            }
+            let mut shelf = self.shelf.write().unwrap();
+            let stored = shelf.remove(order_id)?;
+            self.unqueue(order_id);
+            return Some(then(stored, Location::Shelf));
+        }
+
+        let mut storage = self.storage(location).lock().unwrap();
+        let pos = storage.iter().position(|o| o.order.id == order_id)?;
+        let stored = storage.remove(pos).unwrap();
+        Some(then(stored, location))
+    }
+
+    // picks up a removed order, discarding it instead if it has expired
+    fn hand_out(
+        &self,
+        stored: StoredOrder,
+        location: Location,
+        timestamp: SystemTime,
+    ) -> PickupOutcome {
+        let order_id = stored.order.id.clone();
+        self.release(&order_id);
+        let config = &self.config;
+        let fraction = stored.freshness_fraction(config, timestamp);
+        let target = Unit {
+            location,
+            index: stored.unit,
+        };
+        if stored.is_expired(config, timestamp) {
+            self.record_discard(order_id, target, timestamp, DiscardReason::Missed);
+            PickupOutcome::Expired
+        } else if stored.deadline.is_some_and(|deadline| timestamp > deadline) {
+            self.record_discard(order_id, target, timestamp, DiscardReason::Late);
+            PickupOutcome::Late
+        } else if fraction < config.pickup_freshness_threshold {
+            self.record_discard(order_id, target, timestamp, DiscardReason::Stale);
+            PickupOutcome::Discarded
+        } else {
+            self.pickup_freshness.lock().unwrap().push(fraction);
+            self.record_action(order_id, ActionKind::Pickup, target, timestamp);
+            PickupOutcome::PickedUp
+        }
+    }
+
+    /// Number of actions recorded so far, without touching the ledger. Actions
+    /// taken out by [`Kitchen::drain_actions`] still count.
+    pub fn action_count(&self) -> usize {
+        let counts = self.counters.snapshot();
+        counts.placed + counts.moved + counts.picked_up + counts.discarded
+    }
+
+    /// Actions recorded so far by type and discard reason, without touching the
+    /// ledger. Actions taken out by [`Kitchen::drain_actions`] still count.
+    pub fn action_counts(&self) -> Summary {
+        self.counters.snapshot()
+    }
+
+    /// Distribution of the freshness left on picked-up orders so far.
+    pub fn pickup_stats(&self) -> PickupStats {
+        PickupStats::from_fractions(&self.pickup_freshness.lock().unwrap())
+    }
+
+    /// Distribution of how far scheduled placements ran behind schedule so far.
+    pub fn placement_latency_stats(&self) -> LatencyStats {
+        LatencyStats::from_latencies(&self.placement_latency.lock().unwrap())
+    }
+
+    /// Takes every recorded action out of the kitchen, sorted by timestamp, then
+    /// order id, then the order they were recorded in. Timestamps are strictly
+    /// increasing. The kitchen's ledger is left empty; orders still stored stay
+    /// where they are and later actions are recorded as usual.
+    ///
+    /// With [`KitchenConfig::deterministic`], actions recorded at the same
+    /// timestamp come out in order id order, not in whichever order threads
+    /// happened to record them, so feeding the kitchen the same operations at
+    /// the same timestamps in the same order, as a single thread on a mock
+    /// clock does, reproduces the ledger byte for byte. Concurrent operations
+    /// at one instant can still depend on interleaving when they compete for
+    /// the same slots, since which of them got the slot is a different outcome.
+    ///
+    /// Threads buffer their actions and merge them when a batch fills or the
+    /// thread exits, so call this after joining the workers that did the work.
+    /// A `std::thread::scope` can return before its threads' buffers are
+    /// merged, so join spawned threads when the ledger must be complete.
+    pub fn drain_actions(&self) -> Vec<Action> {
+        ACTION_BUFFERS.with(|buffers| buffers.borrow_mut().flush(&self.actions));
+        let mut actions = std::mem::take(&mut *self.actions.lock().unwrap());
+        self.order_actions(&mut actions);
+        actions
+    }
+
+    /// Like [`Kitchen::drain_actions`], but clones the actions, leaving the
+    /// ledger as it is, for a snapshot mid-run.
+    // the binary only reads the ledger once its run is over, so it drains it
+    #[allow(dead_code)]
+    pub fn get_actions(&self) -> Vec<Action> {
+        ACTION_BUFFERS.with(|buffers| buffers.borrow_mut().flush(&self.actions));
+        let mut actions = self.actions.lock().unwrap().clone();
+        self.order_actions(&mut actions);
+        actions
+    }
+
+    /// Cross-references `orders` against `actions`, a run's whole ledger as
+    /// returned by [`Kitchen::drain_actions`], so an order the run lost track
+    /// of shows up instead of going missing silently.
+    pub fn reconcile(orders: &[Order], actions: &[Action]) -> ReconciliationReport {
+        #[derive(Clone, Copy, PartialEq)]
+        enum State {
+            Stored,
+            Resolved,
+            Impossible,
         }
-    }
 
-    // calc remaining freshness
-    fn remaining_freshness(&self, now: SystemTime) -> i64 {
-        let elapsed = now
-            .duration_since(self.placed_at)
-            .unwrap_or_default()
-            .as_secs() as i64;
+        let mut states: HashMap<&str, State> = HashMap::new();
+        for action in actions {
+            let state = states.get(action.id.as_str()).copied();
+            let next = match (state, action.action) {
+                (None, ActionKind::Place) => State::Stored,
+                (Some(State::Stored), ActionKind::Move) => State::Stored,
+                (Some(State::Stored), ActionKind::Pickup | ActionKind::Discard) => State::Resolved,
+                _ => State::Impossible,
+            };
+            states.insert(&action.id, next);
+        }
 
-        // could optimize this later but works for now
+        let mut report = ReconciliationReport::default();
+        for order in orders {
+            let list = match states.remove(order.id.as_str()) {
+                None => &mut report.unplaced,
+                Some(State::Stored) => &mut report.unresolved,
+                Some(State::Resolved) => continue,
+                Some(State::Impossible) => &mut report.impossible,
+            };
+            list.push(order.id.clone());
+        }
+        let mut unknown: Vec<String> = states.into_keys().map(String::from).collect();
+        unknown.sort();
+        report.impossible.extend(unknown);
+        report
+    }
 
-        let storage_temp = Self::get_storage_temp(&self.current_temp);
-        let degradation_rate = if self.order.temp == storage_temp {
-            DEGRADATION_RATE_IDEAL
-        } else {
-            DEGRADATION_RATE_NON_IDEAL
-        };
+    fn order_actions(&self, actions: &mut [Action]) {
+        actions.sort_by(|a, b| (a.timestamp, &a.id, a.seq).cmp(&(b.timestamp, &b.id, b.seq)));
+        if self.config.deterministic {
+            let mut previous = None;
+            for action in actions {
+                if let Some(previous) = previous
+                    && action.timestamp <= previous
+                {
+                    action.timestamp = previous + 1;
+                }
+                previous = Some(action.timestamp);
+            }
+        }
+    }
+}
 
-        let degraded_freshness = elapsed * degradation_rate;
-        self.order.freshness as i64 - degraded_freshness
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a fixed instant, so tests never depend on the wall clock
+    fn at(secs: f64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000) + Duration::from_secs_f64(secs)
     }
 
-    fn is_expired(&self, now: SystemTime) -> bool {
-        self.remaining_freshness(now) <= 0
+    fn micros(time: SystemTime) -> u64 {
+        time.duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
     }
-}
 
-// priority queue entry
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct OrderEntry {
-    order_id: String,
-    expires_at: i64, // Unix timestamp in microseconds
-}
+    fn order(id: &str, temp: Temp, freshness: u64, price: u64) -> Order {
+        Order {
+            id: id.to_string(),
+            name: id.to_string(),
+            temp,
+            price,
+            freshness,
+            deadline_secs: None,
+        }
+    }
 
-impl Ord for OrderEntry {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.expires_at.cmp(&other.expires_at)
+    fn kitchen() -> Kitchen {
+        Kitchen::new()
     }
-}
 
-impl PartialOrd for OrderEntry {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+    fn place(kitchen: &Kitchen, order: Order, secs: f64) -> Placement {
+        kitchen.place_order(order, at(secs))
     }
-}
 
-pub struct Kitchen {
-    cooler: Arc<Mutex<VecDeque<StoredOrder>>>,
-    heater: Arc<Mutex<VecDeque<StoredOrder>>>,
-    shelf: Arc<Mutex<HashMap<String, StoredOrder>>>,
-    shelf_queue: Arc<Mutex<BinaryHeap<Reverse<OrderEntry>>>>,
+    // Runs `op(thread, iteration)` on `threads` threads, prints the combined rate
+    // to stderr and returns it. The benchmarks only run on request, in release
+    // builds, with the action log on stdout thrown away:
+    // cargo test --release -- --ignored --nocapture bench_ > /dev/null
+    fn bench(
+        name: &str,
+        threads: usize,
+        iterations: usize,
+        op: impl Fn(usize, usize) + Sync,
+    ) -> f64 {
+        let started = Instant::now();
+        std::thread::scope(|scope| {
+            for thread in 0..threads {
+                let op = &op;
+                scope.spawn(move || (0..iterations).for_each(|i| op(thread, i)));
+            }
+        });
+        let rate = (threads * iterations) as f64 / started.elapsed().as_secs_f64();
+        eprintln!("{name}: {rate:.0} ops/s");
+        rate
+    }
 
-    actions: Arc<Mutex<Vec<Action>>>,
+    // prints how the current implementation's rate compares to the old one's
+    fn compare(name: &str, current: f64, baseline: f64) {
+        eprintln!("{name}: {:.2}x the baseline", current / baseline);
+    }
 
-    // make sure timestamps are monotonic
-    last_timestamp: AtomicU64,
-}
+    #[test]
+    fn reset_clears_the_kitchen_for_the_next_scenario() {
+        let kitchen = kitchen();
+        for _ in 0..3 {
+            // more than fits, so the round also moves and discards
+            for idx in 0..30 {
+                let temp = [Temp::Hot, Temp::Cold, Temp::Room][idx % 3];
+                place(&kitchen, order(&idx.to_string(), temp, 60, 10), idx as f64);
+            }
+            kitchen.pickup_order("0", at(40.0));
+            let actions = kitchen.get_actions();
+            // only a reset clock lets a later round start before the last one ended
+            assert_eq!(actions[0].timestamp, micros(at(0.0)));
+            assert!(kitchen.action_counts().discarded > 0);
 
-impl Kitchen {
-    pub fn new() -> Self {
-        Self {
-            cooler: Arc::new(Mutex::new(VecDeque::new())),
-            heater: Arc::new(Mutex::new(VecDeque::new())),
-            shelf: Arc::new(Mutex::new(HashMap::new())),
-            shelf_queue: Arc::new(Mutex::new(BinaryHeap::new())),
-            actions: Arc::new(Mutex::new(Vec::new())),
-            last_timestamp: AtomicU64::new(0),
+            kitchen.reset();
+            assert_eq!(kitchen.action_counts(), Summary::default());
+            assert!(kitchen.get_actions().is_empty());
+            for location in Location::ALL {
+                assert_eq!(kitchen.occupancy(location), 0);
+            }
+            assert_eq!(kitchen.pickup_order("0", at(0.0)), PickupOutcome::Unknown);
         }
     }
 
-    fn record_action(
-        &self,
-        order_id: String,
-        action_type: &str,
-        target: &str,
-        timestamp: SystemTime,
-    ) {
-        let provided_timestamp_micros =
-            timestamp.duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
+    #[test]
+    fn duplicate_id_is_ignored_without_corrupting_the_shelf() {
+        let kitchen = kitchen();
+        let first = order("dup", Temp::Room, 60, 10);
+        assert_eq!(place(&kitchen, first.clone(), 0.0), Placement::Placed);
+        let second = Order {
+            price: 99,
+            ..first.clone()
+        };
+        assert_eq!(place(&kitchen, second, 1.0), Placement::Ignored);
+        assert_eq!(kitchen.occupancy(Location::Shelf), 1);
+        assert_eq!(kitchen.check_invariants(), Ok(()));
 
-        // need to ensure monotonicity across threads
-        let monotonic_timestamp_micros = loop {
-            let last = self.last_timestamp.load(AtomicOrdering::Acquire);
-            let candidate = provided_timestamp_micros.max(last + 1);
-
-            match self.last_timestamp.compare_exchange_weak(
-                last,
-                candidate,
-                AtomicOrdering::Release,
-                AtomicOrdering::Acquire,
-            ) {
-                Ok(_) => break candidate,
-                Err(_actual) => {
-                    // retry
-                    continue;
-                }
+        assert_eq!(
+            kitchen.pickup_order("dup", at(2.0)),
+            PickupOutcome::PickedUp
+        );
+        assert_eq!(kitchen.check_invariants(), Ok(()));
+        let actions = kitchen.get_actions();
+        let kinds: Vec<ActionKind> = actions.iter().map(|action| action.action).collect();
+        assert_eq!(kinds, [ActionKind::Place, ActionKind::Pickup]);
+        assert_eq!(
+            validate_actions(&actions, &[first], kitchen.config()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_read_heavy_shelf() {
+        // Nineteen lookups of a thread's own shelf order for every pickup and
+        // re-placement, the lookups sharing the shelf's read lock, then again
+        // with each lookup taking the write lock, as when the shelf was a Mutex.
+        let run = |name: &str, exclusive: bool| {
+            let kitchen = kitchen();
+            for thread in 0..8 {
+                place(
+                    &kitchen,
+                    order(&format!("t{thread}"), Temp::Room, 3600, 10),
+                    0.0,
+                );
             }
+            bench(name, 8, 100_000, |thread, i| {
+                let id = format!("t{thread}");
+                if i % 20 == 0 {
+                    kitchen.pickup_order(&id, at(1.0));
+                    place(&kitchen, order(&id, Temp::Room, 3600, 10), 1.0);
+                    return;
+                }
+                let freshness = |shelf: &HashMap<String, StoredOrder>| {
+                    shelf
+                        .get(&id)
+                        .map(|stored| stored.remaining_freshness(&kitchen.config, at(1.0)))
+                };
+                if exclusive {
+                    std::hint::black_box(freshness(&kitchen.shelf.write().unwrap()));
+                } else {
+                    std::hint::black_box(freshness(&kitchen.shelf.read().unwrap()));
+                }
+            })
         };
+        let shared = run("read-heavy shelf, read lock", false);
+        let exclusive = run("read-heavy shelf, write lock", true);
+        compare("read-heavy shelf", shared, exclusive);
+    }
 
-        let monotonic_timestamp =
-            UNIX_EPOCH + std::time::Duration::from_micros(monotonic_timestamp_micros);
+    #[test]
+    #[ignore]
+    fn bench_concurrent_recording() {
+        // Batched recording through the per-thread buffers, then again flushing
+        // after every action, which takes the ledger lock once per action as
+        // recording did before the buffers.
+        const ITERATIONS: usize = 50_000;
+        let run = |name: &str, per_action: bool| {
+            let kitchen = kitchen();
+            let shelf = Unit {
+                location: Location::Shelf,
+                index: 0,
+            };
+            let rate = bench(name, 8, ITERATIONS, |thread, i| {
+                let id = format!("t{thread}-{i}");
+                kitchen.record_action(id, ActionKind::Place, shelf, at(0.0));
+                // the scope can end before its threads' buffers are merged
+                if per_action || i == ITERATIONS - 1 {
+                    ACTION_BUFFERS.with(|buffers| buffers.borrow_mut().flush(&kitchen.actions));
+                }
+            });
+            assert_eq!(kitchen.get_actions().len(), 8 * ITERATIONS);
+            rate
+        };
+        let batched = run("concurrent recording, batched", false);
+        let per_action = run("concurrent recording, lock per action", true);
+        compare("concurrent recording", batched, per_action);
+    }
 
-        let action = Action::new(&order_id, action_type, target, monotonic_timestamp);
-        if let Ok(mut actions) = self.actions.lock() {
-            actions.push(action.clone());
-        }
-        println!(
-            "[{}] {}: {} -> {}",
-            monotonic_timestamp_micros, action_type, order_id, target
+    #[test]
+    fn moves_settle_freshness_at_every_hop() {
+        let kitchen = kitchen();
+        place(&kitchen, order("hop", Temp::Hot, 100, 10), 0.0);
+        let remaining = |secs| {
+            kitchen
+                .inspect("hop", at(secs))
+                .unwrap()
+                .remaining_freshness
+        };
+
+        // 10s in the heater at the ideal rate
+        assert!(kitchen.move_order("hop", Location::Shelf, at(10.0)));
+        assert_eq!(remaining(10.0), 90.0);
+        // 10s on the shelf at twice the rate
+        assert!(kitchen.move_order("hop", Location::Cooler, at(20.0)));
+        assert_eq!(remaining(20.0), 70.0);
+        // 5s in the cooler, also twice the rate
+        assert!(kitchen.move_order("hop", Location::Heater, at(25.0)));
+        assert_eq!(remaining(25.0), 60.0);
+        assert_eq!(remaining(35.0), 50.0);
+
+        let inspection = kitchen.inspect("hop", at(35.0)).unwrap();
+        assert_eq!(inspection.location, Location::Heater);
+        assert!(inspection.degrading_ideally && inspection.ever_non_ideal);
+        assert_eq!(inspection.expires_at, at(85.0));
+        assert_eq!(
+            kitchen.pickup_order("hop", at(84.5)),
+            PickupOutcome::PickedUp
         );
     }
 
-    pub fn place_order(&self, order: Order, timestamp: SystemTime) {
-        let stored = StoredOrder {
-            order: order.clone(),
-            placed_at: timestamp,
-            current_temp: String::new(),
-        };
+    #[test]
+    fn freshness_degrades_below_a_second() {
+        let kitchen = kitchen();
+        place(&kitchen, order("a", Temp::Hot, 10, 10), 0.0);
+        let remaining = kitchen.inspect("a", at(0.9)).unwrap().remaining_freshness;
+        assert!((remaining - 9.1).abs() < 1e-6, "{remaining}");
+        assert_eq!(kitchen.pickup_order("a", at(15.0)), PickupOutcome::Expired);
 
-        let ideal_target = match order.temp.as_str() {
-            HOT => HEATER,
-            COLD => COOLER,
-            _ => SHELF,
-        };
+        // twice the rate on the shelf, so 10s of freshness lasts 5s
+        place(&kitchen, order("b", Temp::Hot, 10, 10), 0.0);
+        assert!(kitchen.move_order("b", Location::Shelf, at(0.0)));
+        let remaining = kitchen.inspect("b", at(4.9)).unwrap().remaining_freshness;
+        assert!((remaining - 0.2).abs() < 1e-6, "{remaining}");
+        assert_eq!(kitchen.pickup_order("b", at(5.0)), PickupOutcome::Expired);
+        assert_eq!(kitchen.action_counts().discard_reasons.missed, 2);
+    }
 
-        let placed = if order.temp == HOT || order.temp == COLD {
-            if self.try_place_in_storage(&stored, ideal_target, timestamp) {
-                true
-            } else {
-                self.try_place_on_shelf(&stored, timestamp)
+    #[test]
+    fn where_is_matches_a_full_scan_after_random_operations() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let kitchen = kitchen();
+        let mut rng = StdRng::seed_from_u64(561);
+        let temps = [Temp::Hot, Temp::Cold, Temp::Room];
+        for step in 0..2_000 {
+            let now = at(step as f64 / 10.0);
+            let id = rng.random_range(0..60).to_string();
+            match rng.random_range(0..4) {
+                0 | 1 => {
+                    let temp = temps[rng.random_range(0..temps.len())];
+                    place(&kitchen, order(&id, temp, 120, 10), step as f64 / 10.0);
+                }
+                2 => {
+                    kitchen.pickup_order(&id, now);
+                }
+                _ => {
+                    let to = [Location::Cooler, Location::Heater, Location::Shelf];
+                    kitchen.move_order(&id, to[rng.random_range(0..to.len())], now);
+                }
             }
-        } else {
-            self.try_place_on_shelf(&stored, timestamp)
-        };
+        }
 
-        if !placed {
-            if order.temp == HOT || order.temp == COLD {
-                if self.try_move_to_shelf_from_storage(ideal_target, timestamp) {
-                    self.force_place_in_storage(&stored, ideal_target, timestamp);
-                } else {
-                    if self.try_place_on_shelf(&stored, timestamp) {
-                        return;
-                    }
-                    self.discard_from_shelf(timestamp);
-                    self.force_place_on_shelf(&stored, timestamp);
+        let mut scanned = HashMap::new();
+        kitchen.for_each_stored(at(200.0), |id, location, _| {
+            assert!(
+                scanned.insert(id.to_string(), location).is_none(),
+                "{id} stored twice"
+            );
+        });
+        assert!(!scanned.is_empty());
+        for id in (0..60).map(|id| id.to_string()) {
+            assert_eq!(kitchen.where_is(&id), scanned.get(&id).copied(), "{id}");
+        }
+    }
+
+    // Runs `steps` placements, pickups, moves and cancellations drawn from
+    // `seed` against `kitchen`, a tenth of a second apart, and checks the
+    // invariants after every one. Ids repeat, so some operations hit orders
+    // already resolved or never placed.
+    fn assert_invariants_hold(kitchen: &Kitchen, seed: u64, steps: usize) {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let temps = [Temp::Hot, Temp::Cold, Temp::Room, Temp::Any];
+        let to = [Location::Cooler, Location::Heater, Location::Shelf];
+        for step in 0..steps {
+            let secs = step as f64 / 10.0;
+            let id = rng.random_range(0..80).to_string();
+            let op = match rng.random_range(0..8) {
+                0..4 => {
+                    let temp = temps[rng.random_range(0..temps.len())];
+                    let freshness = rng.random_range(5..120);
+                    let price = rng.random_range(1..50);
+                    place(kitchen, order(&id, temp, freshness, price), secs);
+                    "place"
                 }
-            } else {
-                self.discard_from_shelf(timestamp);
-                self.force_place_on_shelf(&stored, timestamp);
+                4 | 5 => {
+                    kitchen.pickup_order(&id, at(secs));
+                    "pickup"
+                }
+                6 => {
+                    kitchen.move_order(&id, to[rng.random_range(0..to.len())], at(secs));
+                    "move"
+                }
+                _ => {
+                    kitchen.cancel_order(&id, at(secs));
+                    "cancel"
+                }
+            };
+            if let Err(errors) = kitchen.check_invariants() {
+                panic!("seed {seed}, step {step}: {op} {id} broke {errors:?}");
             }
         }
     }
 
-    fn try_place_in_storage(
-        &self,
-        stored: &StoredOrder,
-        target: &str,
-        timestamp: SystemTime,
-    ) -> bool {
-        let mut storage = if target == COOLER {
-            self.cooler.lock().unwrap()
-        } else {
-            self.heater.lock().unwrap()
-        };
+    #[test]
+    fn invariants_hold_after_every_random_operation() {
+        let configs = [
+            KitchenConfig::default(),
+            KitchenConfig {
+                eviction: EvictionStrategy::MinRevenueLoss,
+                ..KitchenConfig::default()
+            },
+            KitchenConfig {
+                overflow_capacity: 4,
+                ..KitchenConfig::default()
+            },
+            KitchenConfig {
+                proactive_rebalance: Some(ProactiveRebalance {
+                    high_water_mark: 0.8,
+                }),
+                move_penalty: Some(1.0),
+                ..KitchenConfig::default()
+            },
+            KitchenConfig {
+                shelf_units: 2,
+                overflow: OverflowPolicy::Reject,
+                ..KitchenConfig::default()
+            },
+        ];
+        for (idx, config) in configs.into_iter().enumerate() {
+            for seed in 0..3 {
+                let kitchen = Kitchen::with_config(config.clone());
+                assert_invariants_hold(&kitchen, idx as u64 * 100 + seed, 3_000);
+                let summary = kitchen.action_counts();
+                assert!(summary.moved > 0, "config {idx} never moved an order");
+                if config.overflow == OverflowPolicy::ForceDiscard {
+                    assert!(
+                        summary.discard_reasons.capacity > 0,
+                        "config {idx} never evicted an order"
+                    );
+                }
+            }
+        }
+    }
 
-        let capacity = if target == COOLER {
-            COOLER_CAPACITY
-        } else {
-            HEATER_CAPACITY
-        };
-        if storage.len() >= capacity {
-            return false;
+    // a burst of placements at one instant, then pickups, some of them sharing an instant
+    fn deterministic_run() -> Vec<Action> {
+        let kitchen = Kitchen::with_config(KitchenConfig {
+            deterministic: true,
+            ..KitchenConfig::default()
+        });
+        let temps = [Temp::Hot, Temp::Cold, Temp::Room];
+        // more than fits, so the burst also moves and discards
+        let orders: Vec<Order> = (0..30)
+            .map(|idx| order(&format!("o{idx:02}"), temps[idx % 3], 20 + idx as u64, 10))
+            .collect();
+        for order in &orders {
+            place(&kitchen, order.clone(), 0.0);
+        }
+        for (idx, order) in orders.iter().enumerate() {
+            kitchen.pickup_order(&order.id, at(5.0 + (idx / 4) as f64));
         }
+        let actions = kitchen.get_actions();
+        let config = KitchenConfig::default();
+        assert_eq!(
+            crate::verify::validate_actions(&actions, &orders, &config),
+            Ok(())
+        );
+        actions
+    }
 
-        let mut stored = stored.clone();
-        stored.current_temp = target.to_string();
-        let order_id = stored.order.id.clone();
-        storage.push_back(stored);
-        self.record_action(order_id, PLACE, target, timestamp);
-        true
+    #[test]
+    fn deterministic_ledger_matches_the_golden_file() {
+        let actions = serde_json::to_string_pretty(&deterministic_run()).unwrap();
+        assert_eq!(
+            actions,
+            include_str!("../testdata/deterministic_actions.json").trim_end()
+        );
     }
 
-    fn try_place_on_shelf(&self, stored: &StoredOrder, timestamp: SystemTime) -> bool {
-        let mut shelf = self.shelf.lock().unwrap();
-        if shelf.len() >= SHELF_CAPACITY {
-            return false;
+    #[test]
+    fn removal_searches_cooler_then_heater_then_shelf() {
+        let kitchen = kitchen();
+        place(&kitchen, order("x", Temp::Hot, 60, 10), 0.0);
+        // plant copies where a correct kitchen never has them and lose the index,
+        // so only the fallback search can find them
+        let stored = kitchen.heater.lock().unwrap()[0].clone();
+        kitchen.cooler.lock().unwrap().push_back(stored.clone());
+        kitchen
+            .shelf
+            .write()
+            .unwrap()
+            .insert("x".to_string(), stored);
+        kitchen.locations.lock().unwrap().remove("x");
+
+        let removed: Vec<Option<Location>> = (0..4)
+            .map(|_| kitchen.remove_order("x", &|_, location| location))
+            .collect();
+        assert_eq!(
+            removed,
+            [
+                Some(Location::Cooler),
+                Some(Location::Heater),
+                Some(Location::Shelf),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn flexible_orders_spread_across_locations() {
+        let kitchen = kitchen();
+        for idx in 0..12 {
+            place(&kitchen, order(&idx.to_string(), Temp::Any, 60, 10), 0.0);
         }
+        // half of every location, rather than a full shelf
+        assert_eq!(kitchen.occupancy(Location::Shelf), 6);
+        assert_eq!(kitchen.occupancy(Location::Cooler), 3);
+        assert_eq!(kitchen.occupancy(Location::Heater), 3);
+        // the first one found everything equally empty and took the shelf
+        assert_eq!(kitchen.where_is("0"), Some(Location::Shelf));
+    }
 
-        let mut stored = stored.clone();
-        stored.current_temp = SHELF.to_string();
+    #[test]
+    fn an_order_is_never_recorded_before_its_placement() {
+        let kitchen = kitchen();
+        place(&kitchen, order("late", Temp::Room, 60, 10), 10.0);
+        // the pickup thread read the clock before the placement thread did
+        assert_eq!(
+            kitchen.pickup_order("late", at(9.5)),
+            PickupOutcome::PickedUp
+        );
 
-        let order_id = stored.order.id.clone();
-        let expires_at = self.calculate_expiration(&stored, stored.placed_at);
-        let entry = OrderEntry {
-            order_id: order_id.clone(),
-            expires_at,
-        };
+        let actions = kitchen.get_actions();
+        let kinds: Vec<ActionKind> = actions.iter().map(|action| action.action).collect();
+        assert_eq!(kinds, [ActionKind::Place, ActionKind::Pickup]);
+        assert_eq!(actions[0].timestamp, micros(at(10.0)));
+        assert_eq!(actions[1].timestamp, micros(at(10.0)) + 1);
+    }
 
-        shelf.insert(order_id.clone(), stored);
+    #[test]
+    fn for_each_stored_visits_every_order_with_its_freshness() {
+        let kitchen = kitchen();
+        place(&kitchen, order("hot", Temp::Hot, 60, 10), 0.0);
+        place(&kitchen, order("cold", Temp::Cold, 60, 10), 0.0);
+        place(&kitchen, order("room", Temp::Room, 60, 10), 0.0);
+        assert!(kitchen.move_order("hot", Location::Shelf, at(0.0)));
 
-        self.shelf_queue.lock().unwrap().push(Reverse(entry));
-        drop(shelf);
-        self.record_action(order_id, PLACE, SHELF, timestamp);
-        true
+        let mut seen = Vec::new();
+        kitchen.for_each_stored(at(10.0), |id, location, remaining| {
+            seen.push((id.to_string(), location, remaining));
+        });
+        seen.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            seen,
+            [
+                ("cold".to_string(), Location::Cooler, 50.0),
+                ("hot".to_string(), Location::Shelf, 40.0),
+                ("room".to_string(), Location::Shelf, 50.0),
+            ]
+        );
     }
 
-    fn force_place_on_shelf(&self, stored: &StoredOrder, timestamp: SystemTime) {
-        let mut shelf = self.shelf.lock().unwrap();
+    #[test]
+    fn a_rebuilt_kitchen_carries_on_where_the_ledger_stopped() {
+        let orders = [
+            order("gone", Temp::Cold, 60, 10),
+            order("moved", Temp::Hot, 100, 10),
+            order("stays", Temp::Room, 60, 10),
+        ];
+        let original = kitchen();
+        for order in &orders {
+            place(&original, order.clone(), 0.0);
+        }
+        original.pickup_order("gone", at(5.0));
+        original.move_order("moved", Location::Shelf, at(10.0));
+        // after the cut, so it isn't replayed
+        original.pickup_order("stays", at(30.0));
+        let actions = original.get_actions();
+
+        let rebuilt =
+            Kitchen::from_actions(KitchenConfig::default(), &orders, &actions, at(20.0)).unwrap();
+        assert_eq!(rebuilt.where_is("gone"), None);
+        assert_eq!(rebuilt.where_is("moved"), Some(Location::Shelf));
+        assert_eq!(rebuilt.where_is("stays"), Some(Location::Shelf));
+        assert_eq!(rebuilt.check_invariants(), Ok(()));
+        // 10s in the heater, then 10s on the shelf at twice the rate, give or
+        // take the microseconds the ledger nudged timestamps apart by
+        let remaining = rebuilt
+            .inspect("moved", at(20.0))
+            .unwrap()
+            .remaining_freshness;
+        assert!((remaining - 70.0).abs() < 1e-3, "{remaining}");
+
+        assert_eq!(
+            rebuilt.pickup_order("gone", at(21.0)),
+            PickupOutcome::AlreadyPickedUp
+        );
+        assert_eq!(
+            rebuilt.pickup_order("moved", at(21.0)),
+            PickupOutcome::PickedUp
+        );
+        let ledger = rebuilt.get_actions();
+        // three places, a pickup and a move replayed, then the new pickup
+        assert_eq!(ledger.len(), 6);
+        assert_eq!(validate_actions(&ledger, &orders, rebuilt.config()), Ok(()));
+        let last = ledger.last().unwrap();
+        assert_eq!(
+            (last.id.as_str(), last.action),
+            ("moved", ActionKind::Pickup)
+        );
+    }
 
-        if shelf.len() >= SHELF_CAPACITY {
-            panic!("force_place_on_shelf called when shelf is full");
+    #[test]
+    fn pickup_threshold_discards_nearly_spoiled_orders() {
+        for (threshold, outcome) in [
+            (0.05, PickupOutcome::Discarded),
+            (0.0, PickupOutcome::PickedUp),
+        ] {
+            let kitchen = Kitchen::with_config(KitchenConfig {
+                pickup_freshness_threshold: threshold,
+                ..KitchenConfig::default()
+            });
+            place(&kitchen, order("x", Temp::Room, 100, 10), 0.0);
+            // 2% of its freshness left
+            assert_eq!(kitchen.pickup_order("x", at(98.0)), outcome, "{threshold}");
+            let stale = usize::from(outcome == PickupOutcome::Discarded);
+            assert_eq!(kitchen.action_counts().discard_reasons.stale, stale);
         }
+    }
 
-        let mut stored = stored.clone();
-        stored.current_temp = SHELF.to_string();
+    #[test]
+    fn the_shelf_cannot_fill_while_an_order_moves_onto_it() {
+        use std::sync::mpsc;
 
-        let order_id = stored.order.id.clone();
-        let expires_at = self.calculate_expiration(&stored, stored.placed_at);
-        let entry = OrderEntry {
-            order_id: order_id.clone(),
-            expires_at,
-        };
+        let kitchen = kitchen();
+        for idx in 0..HEATER_CAPACITY {
+            place(
+                &kitchen,
+                order(&format!("hot{idx}"), Temp::Hot, 60, 10),
+                0.0,
+            );
+        }
+        for idx in 0..SHELF_CAPACITY {
+            place(
+                &kitchen,
+                order(&format!("room{idx}"), Temp::Room, 60, 10),
+                0.0,
+            );
+        }
 
-        shelf.insert(order_id.clone(), stored);
-        self.shelf_queue.lock().unwrap().push(Reverse(entry));
-        drop(shelf);
-        self.record_action(order_id, PLACE, SHELF, timestamp);
+        // the moment an eviction frees a shelf slot for the heater's oldest order,
+        // before that order has moved into it, another thread goes for the shelf
+        let (evicted, wake) = mpsc::channel();
+        let evicted = Mutex::new(Some(evicted));
+        kitchen.on_action(Box::new(move |action| {
+            if action.action == ActionKind::Discard
+                && let Some(evicted) = evicted.lock().unwrap().take()
+            {
+                evicted.send(()).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }));
+        std::thread::scope(|scope| {
+            let kitchen = &kitchen;
+            scope.spawn(move || {
+                wake.recv_timeout(Duration::from_secs(10))
+                    .expect("nothing was evicted");
+                place(kitchen, order("rival", Temp::Room, 60, 10), 1.0);
+            });
+            place(kitchen, order("incoming", Temp::Hot, 60, 10), 1.0);
+        });
+
+        assert_eq!(kitchen.check_invariants(), Ok(()));
+        assert_eq!(kitchen.occupancy(Location::Heater), HEATER_CAPACITY);
+        assert_eq!(kitchen.occupancy(Location::Shelf), SHELF_CAPACITY);
+        assert_eq!(kitchen.where_is("incoming"), Some(Location::Heater));
+        // one eviction for the move, and the rival found the shelf full again
+        assert_eq!(kitchen.action_counts().discard_reasons.capacity, 2);
     }
 
-    fn force_place_in_storage(&self, stored: &StoredOrder, target: &str, timestamp: SystemTime) {
-        let mut storage = if target == COOLER {
-            self.cooler.lock().unwrap()
-        } else {
-            self.heater.lock().unwrap()
-        };
+    #[test]
+    fn forced_moves_never_overfill_the_shelf_under_contention() {
+        const THREADS: usize = 4;
+        const PER_THREAD: usize = 300;
+        let temps = [Temp::Hot, Temp::Cold, Temp::Room];
+        let orders: Vec<Order> = (0..THREADS * PER_THREAD)
+            .map(|idx| order(&idx.to_string(), temps[idx % 3], 600, 10))
+            .collect();
 
-        let capacity = if target == COOLER {
-            COOLER_CAPACITY
-        } else {
-            HEATER_CAPACITY
-        };
-        if storage.len() >= capacity {
-            panic!("force_place_in_storage called when storage is full");
+        let kitchen = Arc::new(kitchen());
+        let orders = Arc::new(orders);
+        // joined rather than scoped, so each thread has flushed its buffered
+        // actions to the ledger by the time it is joined
+        let handles: Vec<_> = (0..THREADS)
+            .map(|thread| {
+                let (kitchen, orders) = (kitchen.clone(), orders.clone());
+                std::thread::spawn(move || {
+                    // hot and cold orders arriving at full storage take the move path,
+                    // while pickups of orders from other threads free slots
+                    for i in 0..PER_THREAD {
+                        let idx = i * THREADS + thread;
+                        place(&kitchen, orders[idx].clone(), i as f64);
+                        if i >= 20 && i % 2 == 0 {
+                            let earlier = (i - 20) * THREADS + (thread + 1) % THREADS;
+                            kitchen.pickup_order(&orders[earlier].id, at(i as f64));
+                        }
+                        assert_eq!(kitchen.check_invariants(), Ok(()), "{idx}");
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
         }
 
-        let mut stored = stored.clone();
-        stored.current_temp = target.to_string();
-        let order_id = stored.order.id.clone();
-        storage.push_back(stored);
-        self.record_action(order_id, PLACE, target, timestamp);
+        let counts = kitchen.action_counts();
+        assert!(counts.moved > 0 && counts.discard_reasons.capacity > 0);
+        let actions = kitchen.get_actions();
+        assert_eq!(
+            validate_actions(&actions, &orders, kitchen.config()),
+            Ok(())
+        );
     }
 
-    fn try_move_to_shelf_from_storage(&self, source: &str, timestamp: SystemTime) -> bool {
-        let shelf = self.shelf.lock().unwrap();
-        if shelf.len() >= SHELF_CAPACITY {
-            drop(shelf);
-            self.discard_from_shelf(timestamp);
-        } else {
-            drop(shelf);
+    #[test]
+    fn inspection_explains_where_an_order_degrades() {
+        let kitchen = kitchen();
+        for idx in 0..HEATER_CAPACITY {
+            place(&kitchen, order(&format!("h{idx}"), Temp::Hot, 60, 10), 0.0);
         }
+        place(&kitchen, order("late", Temp::Hot, 60, 10), 10.0);
 
-        let mut storage = if source == COOLER {
-            self.cooler.lock().unwrap()
-        } else {
-            self.heater.lock().unwrap()
+        let inspection = kitchen.inspect("late", at(15.0)).unwrap();
+        assert_eq!(inspection.location, Location::Shelf);
+        assert_eq!(inspection.ideal_location, Some(Location::Heater));
+        assert!(!inspection.degrading_ideally && inspection.ever_non_ideal);
+        // twice as fast on the shelf: 10 of its 60 seconds gone in 5
+        assert!((inspection.remaining_freshness - 50.0).abs() < 1e-3);
+        let expires_in = inspection.expires_at.duration_since(at(15.0)).unwrap();
+        assert!((expires_in.as_secs_f64() - 25.0).abs() < 1e-3);
+        assert_eq!(
+            inspection.to_string(),
+            "late (hot) on the shelf, 50.0s fresh, degrading faster than on the heater"
+        );
+
+        let settled = kitchen.inspect("h0", at(15.0)).unwrap();
+        assert_eq!(settled.to_string(), "h0 (hot) on the heater, 45.0s fresh");
+        assert!(kitchen.inspect("missing", at(15.0)).is_none());
+
+        // the pickup frees a heater slot for the late order
+        kitchen.pickup_order("h0", at(15.0));
+        assert_eq!(
+            kitchen.inspect("late", at(20.0)).unwrap().to_string(),
+            "late (hot) on the heater, 45.0s fresh, back where it keeps best after time away"
+        );
+    }
+
+    #[test]
+    fn a_pickup_just_past_expiry_counts_as_missed() {
+        let kitchen = kitchen();
+        place(&kitchen, order("late", Temp::Room, 10, 10), 0.0);
+        place(&kitchen, order("early", Temp::Room, 10, 10), 0.0);
+
+        let micro = Duration::from_micros(1);
+        assert_eq!(
+            kitchen.pickup_order("late", at(10.0) + micro),
+            PickupOutcome::Expired
+        );
+        assert_eq!(
+            kitchen.pickup_order("early", at(10.0) - micro),
+            PickupOutcome::PickedUp
+        );
+
+        let summary = kitchen.action_counts();
+        assert_eq!((summary.picked_up, summary.discarded), (1, 1));
+        assert_eq!(
+            summary.discard_reasons,
+            DiscardCounts {
+                missed: 1,
+                ..DiscardCounts::default()
+            }
+        );
+        let actions = kitchen.get_actions();
+        let late = actions.iter().rfind(|action| action.id == "late").unwrap();
+        assert_eq!(late.action, ActionKind::Discard);
+    }
+
+    #[test]
+    fn proactive_rebalance_forces_fewer_discards() {
+        // expired heater orders nobody picked up, a shelf of hot orders waiting
+        // for their slots, and a rush of room orders that only fit on the shelf
+        let capacity_discards = |proactive_rebalance| {
+            let kitchen = Kitchen::with_config(KitchenConfig {
+                proactive_rebalance,
+                ..KitchenConfig::default()
+            });
+            for idx in 0..HEATER_CAPACITY {
+                place(
+                    &kitchen,
+                    order(&format!("stale{idx}"), Temp::Hot, 5, 10),
+                    0.0,
+                );
+            }
+            for idx in 0..SHELF_CAPACITY {
+                place(
+                    &kitchen,
+                    order(&format!("hot{idx}"), Temp::Hot, 300, 10),
+                    1.0,
+                );
+            }
+            for idx in 0..HEATER_CAPACITY {
+                place(
+                    &kitchen,
+                    order(&format!("room{idx}"), Temp::Room, 300, 10),
+                    10.0,
+                );
+            }
+            assert_eq!(kitchen.check_invariants(), Ok(()));
+            kitchen.action_counts().discard_reasons.capacity
         };
 
-        if storage.is_empty() {
-            drop(storage);
-            return false;
+        let reactive = capacity_discards(None);
+        let proactive = capacity_discards(Some(ProactiveRebalance {
+            high_water_mark: 0.8,
+        }));
+        // the stale orders make way for the waiting hot ones, which make way
+        // for the rush
+        assert_eq!(reactive, HEATER_CAPACITY);
+        assert_eq!(proactive, 0);
+    }
+
+    #[test]
+    fn min_revenue_loss_evicts_the_cheap_order_for_an_expensive_one() {
+        let kitchen = Kitchen::with_config(KitchenConfig {
+            eviction: EvictionStrategy::MinRevenueLoss,
+            ..KitchenConfig::default()
+        });
+        // the cheap order is the freshest, so earliest expiry would keep it
+        place(&kitchen, order("cheap", Temp::Room, 600, 1), 0.0);
+        for idx in 1..SHELF_CAPACITY {
+            place(
+                &kitchen,
+                order(&format!("dear{idx}"), Temp::Room, 300, 100),
+                0.0,
+            );
         }
 
-        let stored = storage.pop_front().unwrap();
-        let order_id = stored.order.id.clone();
-        drop(storage);
+        let expensive = place(&kitchen, order("expensive", Temp::Room, 300, 50), 1.0);
+        assert_eq!(expensive, Placement::Placed);
+        assert_eq!(kitchen.where_is("cheap"), None);
+        assert_eq!(kitchen.where_is("expensive"), Some(Location::Shelf));
 
-        let mut moved = stored.clone();
-        moved.current_temp = SHELF.to_string();
+        // an arriving order worth less than everything on the shelf is the one to lose
+        let cheaper = place(&kitchen, order("cheaper", Temp::Room, 600, 1), 2.0);
+        assert_eq!(cheaper, Placement::Rejected);
+        assert_eq!(kitchen.where_is("cheaper"), None);
+        assert_eq!(kitchen.occupancy(Location::Shelf), SHELF_CAPACITY);
 
-        let mut shelf = self.shelf.lock().unwrap();
-        let expires_at = self.calculate_expiration(&moved, moved.placed_at);
-        let entry = OrderEntry {
-            order_id: order_id.clone(),
-            expires_at,
-        };
+        let actions = kitchen.drain_actions();
+        let discards: Vec<&str> = actions
+            .iter()
+            .filter(|action| action.action == ActionKind::Discard)
+            .map(|action| action.id.as_str())
+            .collect();
+        assert_eq!(discards, ["cheap"]);
+        assert!(!actions.iter().any(|action| action.id == "cheaper"));
+        assert_eq!(kitchen.check_invariants(), Ok(()));
+    }
 
-        shelf.insert(order_id.clone(), moved);
-        self.shelf_queue.lock().unwrap().push(Reverse(entry));
-        drop(shelf);
-        self.record_action(order_id, MOVE, SHELF, timestamp);
-        true
+    #[test]
+    fn running_counts_match_the_ledger() {
+        let kitchen = Kitchen::new();
+        assert_invariants_hold(&kitchen, 7, 1_000);
+        let counts = kitchen.action_counts();
+        assert!(counts.moved > 0 && counts.discard_reasons.capacity > 0);
+        assert_eq!(Summary::from_actions(&kitchen.drain_actions()), counts);
     }
 
-    fn discard_from_shelf(&self, timestamp: SystemTime) {
-        let mut shelf = self.shelf.lock().unwrap();
-        let mut queue = self.shelf_queue.lock().unwrap();
+    #[test]
+    fn get_actions_leaves_the_ledger_for_drain_actions() {
+        let kitchen = kitchen();
+        place(&kitchen, order("b", Temp::Room, 60, 10), 1.0);
+        place(&kitchen, order("a", Temp::Hot, 60, 10), 0.0);
 
-        while let Some(Reverse(entry)) = queue.pop() {
-            if let Some(_stored) = shelf.remove(&entry.order_id) {
-                self.record_action(entry.order_id, DISCARD, SHELF, timestamp);
-                return;
-            }
-        }
+        let stamps = |actions: Vec<Action>| -> Vec<(String, u64)> {
+            actions
+                .into_iter()
+                .map(|action| (action.id, action.timestamp))
+                .collect()
+        };
+        let snapshot = stamps(kitchen.get_actions());
+        // the shared clock keeps the later call later
+        assert_eq!(snapshot[0].0, "b");
+        assert_eq!(snapshot[1].0, "a");
+        assert!(snapshot[0].1 < snapshot[1].1);
+        assert_eq!(stamps(kitchen.get_actions()), snapshot);
+        assert_eq!(stamps(kitchen.drain_actions()), snapshot);
+        assert!(kitchen.get_actions().is_empty());
+    }
+
+    #[test]
+    fn action_count_matches_the_drained_ledger() {
+        let kitchen = kitchen();
+        assert_invariants_hold(&kitchen, 11, 500);
+        let before = kitchen.action_count();
+        let actions = kitchen.drain_actions();
+        assert_eq!(before, actions.len());
+        // draining leaves the count alone, and later actions add to it
+        assert_eq!(kitchen.action_count(), actions.len());
+        place(&kitchen, order("later", Temp::Room, 60, 10), 10_000.0);
+        assert_eq!(kitchen.action_count(), actions.len() + 1);
+        assert_eq!(kitchen.drain_actions().len(), 1);
+    }
 
-        if shelf.is_empty() {
-            panic!("discard_from_shelf called but shelf is empty");
+    #[test]
+    fn reconcile_sorts_orders_by_what_became_of_them() {
+        let kitchen = kitchen();
+        let orders: Vec<Order> = ["done", "waiting", "lost", "twice"]
+            .into_iter()
+            .map(|id| order(id, Temp::Room, 60, 10))
+            .collect();
+        for order in &orders[..2] {
+            place(&kitchen, order.clone(), 0.0);
         }
-        panic!("discard_from_shelf failed");
+        kitchen.pickup_order("done", at(1.0));
+        let mut actions = kitchen.drain_actions();
+        for (id, kind) in [
+            ("twice", ActionKind::Place),
+            ("twice", ActionKind::Place),
+            ("stray", ActionKind::Place),
+        ] {
+            actions.push(Action::new(id, kind, Location::Shelf, at(2.0)));
+        }
+
+        let report = Kitchen::reconcile(&orders, &actions);
+        assert_eq!(report.unplaced, ["lost"]);
+        assert_eq!(report.unresolved, ["waiting"]);
+        assert_eq!(report.impossible, ["twice", "stray"]);
+        assert!(!report.is_clean());
+        let done: Vec<Action> = actions
+            .into_iter()
+            .filter(|action| action.id == "done")
+            .collect();
+        assert!(Kitchen::reconcile(&orders[..1], &done).is_clean());
     }
 
-    fn calculate_expiration(&self, stored: &StoredOrder, _now: SystemTime) -> i64 {
-        let storage_temp = StoredOrder::get_storage_temp(&stored.current_temp);
-        let degradation_rate = if stored.order.temp == storage_temp {
-            DEGRADATION_RATE_IDEAL
-        } else {
-            DEGRADATION_RATE_NON_IDEAL
-        };
+    #[test]
+    fn a_waiting_placement_takes_a_slot_freed_in_time() {
+        let kitchen = Kitchen::with_config(KitchenConfig {
+            overflow: OverflowPolicy::WaitForSlot {
+                timeout: Some(Duration::from_secs(30)),
+                fallback: WaitFallback::ForceDiscard,
+            },
+            ..KitchenConfig::default()
+        });
+        for idx in 0..HEATER_CAPACITY + SHELF_CAPACITY {
+            place(&kitchen, order(&idx.to_string(), Temp::Hot, 300, 10), 0.0);
+        }
 
-        let seconds_until_expiration = stored.order.freshness as f64 / degradation_rate as f64;
-        let microseconds_until_expiration = (seconds_until_expiration * 1_000_000.0) as u64;
+        let started = Instant::now();
+        std::thread::scope(|scope| {
+            let waiter = scope.spawn(|| place(&kitchen, order("waiter", Temp::Hot, 300, 10), 1.0));
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!waiter.is_finished(), "placed without waiting");
+            kitchen.pickup_order("0", at(2.0));
+            assert_eq!(waiter.join().unwrap(), Placement::Placed);
+        });
 
-        stored
-            .placed_at
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_micros() as i64
-            + microseconds_until_expiration as i64
+        assert!(started.elapsed() < Duration::from_secs(30));
+        assert!(kitchen.where_is("waiter").is_some());
+        assert_eq!(kitchen.action_counts().discarded, 0);
+        assert_eq!(kitchen.check_invariants(), Ok(()));
     }
 
-    pub fn pickup_order(&self, order_id: &str, timestamp: SystemTime) {
-        // check cooler first
-        {
-            let mut cooler = self.cooler.lock().unwrap();
-            if let Some(pos) = cooler.iter().position(|o| o.order.id == order_id) {
-                let stored = cooler.remove(pos).unwrap();
-                if stored.is_expired(timestamp) {
-                    self.record_action(order_id.to_string(), DISCARD, COOLER, timestamp);
-                } else {
-                    self.record_action(order_id.to_string(), PICKUP, COOLER, timestamp);
+    #[test]
+    fn emptying_the_shelf_mid_placement_never_panics() {
+        const ROUNDS: usize = 200;
+        const EXTRA: usize = 4;
+        let kitchen = Arc::new(kitchen());
+        let nothing = StoredOrder::new(order("nothing", Temp::Room, 600, 10), at(0.0));
+        assert_eq!(
+            kitchen.discard_from_shelf(&nothing, at(0.0)),
+            Eviction::Empty
+        );
+        assert!(kitchen.get_actions().is_empty());
+
+        let round_order =
+            |round: usize, idx: usize| order(&format!("{round}-{idx}"), Temp::Room, 600, 10);
+        let orders: Vec<Order> = (0..ROUNDS)
+            .flat_map(|round| (0..SHELF_CAPACITY + EXTRA).map(move |idx| round_order(round, idx)))
+            .collect();
+
+        // each round the shelf starts full; one thread overfills it while the
+        // other picks everything up, so a placement can find it full and then
+        // have nothing left to discard
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let placer = {
+            let (kitchen, barrier) = (kitchen.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                for round in 0..ROUNDS {
+                    barrier.wait();
+                    for idx in SHELF_CAPACITY..SHELF_CAPACITY + EXTRA {
+                        place(&kitchen, round_order(round, idx), round as f64 + 0.5);
+                    }
+                    barrier.wait();
                 }
-                return;
+            })
+        };
+        let picker = {
+            let (kitchen, barrier) = (kitchen.clone(), barrier.clone());
+            std::thread::spawn(move || {
+                for round in 0..ROUNDS {
+                    barrier.wait();
+                    for idx in (0..SHELF_CAPACITY).rev() {
+                        kitchen.pickup_order(&format!("{round}-{idx}"), at(round as f64 + 0.5));
+                    }
+                    barrier.wait();
+                }
+            })
+        };
+        for round in 0..ROUNDS {
+            for idx in 0..SHELF_CAPACITY {
+                place(&kitchen, round_order(round, idx), round as f64);
             }
+            barrier.wait();
+            barrier.wait();
+            assert_eq!(kitchen.check_invariants(), Ok(()), "round {round}");
         }
+        placer.join().unwrap();
+        picker.join().unwrap();
 
-        {
-            let mut heater = self.heater.lock().unwrap();
-            if let Some(pos) = heater.iter().position(|o| o.order.id == order_id) {
-                let stored = heater.remove(pos).unwrap();
-                if stored.is_expired(timestamp) {
-                    self.record_action(order_id.to_string(), DISCARD, HEATER, timestamp);
-                } else {
-                    self.record_action(order_id.to_string(), PICKUP, HEATER, timestamp);
-                }
-                return;
-            }
+        assert_eq!(
+            validate_actions(&kitchen.get_actions(), &orders, kitchen.config()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn a_fresh_pickup_past_its_deadline_is_late() {
+        let kitchen = kitchen();
+        for id in ["on-time", "late"] {
+            let order = Order {
+                deadline_secs: Some(5),
+                ..order(id, Temp::Room, 60, 10)
+            };
+            place(&kitchen, order, 0.0);
         }
 
-        // then shelf
-        {
-            let mut shelf = self.shelf.lock().unwrap();
-            if let Some(stored) = shelf.remove(&order_id.to_string()) {
-                let mut queue = self.shelf_queue.lock().unwrap();
-                queue.retain(|Reverse(entry)| entry.order_id != order_id);
-                drop(queue);
+        assert_eq!(
+            kitchen.pickup_order("on-time", at(5.0)),
+            PickupOutcome::PickedUp
+        );
+        assert_eq!(kitchen.pickup_order("late", at(6.0)), PickupOutcome::Late);
+        let summary = kitchen.action_counts();
+        assert_eq!(
+            summary.discard_reasons,
+            DiscardCounts {
+                late: 1,
+                ..DiscardCounts::default()
+            }
+        );
+    }
 
-                if stored.is_expired(timestamp) {
-                    self.record_action(order_id.to_string(), DISCARD, SHELF, timestamp);
-                } else {
-                    self.record_action(order_id.to_string(), PICKUP, SHELF, timestamp);
-                }
+    #[test]
+    fn moves_happen_only_when_they_beat_the_penalty() {
+        let penalized = |move_penalty| {
+            Kitchen::with_config(KitchenConfig {
+                move_penalty: Some(move_penalty),
+                ..KitchenConfig::default()
+            })
+        };
+
+        // Back to the heater after a pickup: the hot order has 40 of its 60
+        // seconds left at 10s, worth $6.67, and halving its rate saves half that.
+        let rebalanced = |move_penalty| {
+            let kitchen = penalized(move_penalty);
+            for idx in 0..HEATER_CAPACITY {
+                place(&kitchen, order(&format!("h{idx}"), Temp::Hot, 600, 10), 0.0);
             }
+            place(&kitchen, order("waiting", Temp::Hot, 60, 10), 0.0);
+            kitchen.pickup_order("h0", at(10.0));
+            kitchen.where_is("waiting") == Some(Location::Heater)
+        };
+        assert!(rebalanced(3.3));
+        assert!(!rebalanced(3.4));
+
+        // Making room: the new order gains $10 from its ideal slot and the
+        // oldest, half spoiled, loses $2.50 on the shelf.
+        let made_room = |move_penalty| {
+            let kitchen = penalized(move_penalty);
+            for idx in 0..HEATER_CAPACITY {
+                place(&kitchen, order(&format!("h{idx}"), Temp::Hot, 60, 10), 0.0);
+            }
+            for idx in 0..SHELF_CAPACITY {
+                place(
+                    &kitchen,
+                    order(&format!("r{idx}"), Temp::Room, 600, 10),
+                    0.0,
+                );
+            }
+            place(&kitchen, order("new", Temp::Hot, 60, 20), 30.0);
+            assert_eq!(kitchen.check_invariants(), Ok(()));
+            kitchen.where_is("new") == Some(Location::Heater)
+        };
+        assert!(made_room(7.4));
+        assert!(!made_room(7.6));
+    }
+
+    #[test]
+    fn distance_degradation_spoils_hot_orders_fastest_in_the_cooler() {
+        let kitchen = Kitchen::with_config(KitchenConfig {
+            degradation: DegradationModel::Distance {
+                ideal: 1,
+                per_step: 1,
+            },
+            ..KitchenConfig::default()
+        });
+        for (id, to) in [("shelf", Location::Shelf), ("cooler", Location::Cooler)] {
+            place(&kitchen, order(id, Temp::Hot, 60, 10), 0.0);
+            assert!(kitchen.move_order(id, to, at(0.0)));
         }
+        place(&kitchen, order("heater", Temp::Hot, 60, 10), 0.0);
+
+        // room is one step from hot and cold two
+        let remaining = |id| kitchen.inspect(id, at(10.0)).unwrap().remaining_freshness;
+        assert!((remaining("heater") - 50.0).abs() < 1e-3);
+        assert!((remaining("shelf") - 40.0).abs() < 1e-3);
+        assert!((remaining("cooler") - 30.0).abs() < 1e-3);
     }
 
-    pub fn get_actions(&self) -> Vec<Action> {
-        let mut actions = self.actions.lock().unwrap().clone();
-        actions.sort_by_key(|a| a.timestamp);
-        actions
+    #[test]
+    fn exponential_decay_expires_at_its_threshold() {
+        let kitchen = Kitchen::with_config(KitchenConfig {
+            curve: Arc::new(Exponential {
+                decay: 0.1,
+                threshold: 1.0,
+            }),
+            ..KitchenConfig::default()
+        });
+        for id in ["early", "late"] {
+            place(&kitchen, order(id, Temp::Room, 10, 10), 0.0);
+        }
+
+        let remaining = kitchen
+            .inspect("early", at(10.0))
+            .unwrap()
+            .remaining_freshness;
+        assert!((remaining - 10.0 * 0.9f64.powi(10)).abs() < 1e-3);
+        // a tenth of the freshness left after ln(0.1) / ln(0.9), about 21.85 seconds
+        assert_eq!(
+            kitchen.pickup_order("early", at(21.8)),
+            PickupOutcome::PickedUp
+        );
+        assert_eq!(
+            kitchen.pickup_order("late", at(21.9)),
+            PickupOutcome::Expired
+        );
     }
 }
@@ -0,0 +1,104 @@
+//! Lock wrappers that count acquisitions and time spent waiting for them.
+//!
+//! Counting only happens with the `metrics` feature; without it the wrappers
+//! forward straight to the std locks and have nothing to report.
+
+use std::sync::{LockResult, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use std::time::{Duration, Instant};
+
+/// Totals for a single lock.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStat {
+    pub acquisitions: u64,
+    pub wait: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    #[cfg(feature = "metrics")]
+    acquisitions: AtomicU64,
+    #[cfg(feature = "metrics")]
+    wait_nanos: AtomicU64,
+}
+
+impl Counter {
+    #[inline]
+    fn track<G>(&self, acquire: impl FnOnce() -> G) -> G {
+        #[cfg(feature = "metrics")]
+        {
+            let start = Instant::now();
+            let guard = acquire();
+            let waited = start.elapsed().as_nanos() as u64;
+            self.acquisitions.fetch_add(1, Ordering::Relaxed);
+            self.wait_nanos.fetch_add(waited, Ordering::Relaxed);
+            guard
+        }
+        #[cfg(not(feature = "metrics"))]
+        acquire()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn stat(&self) -> LockStat {
+        LockStat {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            wait: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TrackedMutex<T> {
+    inner: Mutex<T>,
+    counter: Counter,
+}
+
+impl<T> TrackedMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+            counter: Counter::default(),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        self.counter.track(|| self.inner.lock())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stat(&self) -> LockStat {
+        self.counter.stat()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TrackedRwLock<T> {
+    inner: RwLock<T>,
+    counter: Counter,
+}
+
+impl<T> TrackedRwLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: RwLock::new(value),
+            counter: Counter::default(),
+        }
+    }
+
+    pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+        self.counter.track(|| self.inner.read())
+    }
+
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+        self.counter.track(|| self.inner.write())
+    }
+
+    #[cfg(feature = "metrics")]
+    pub fn stat(&self) -> LockStat {
+        self.counter.stat()
+    }
+}
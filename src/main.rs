@@ -1,24 +1,80 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use clap::Parser;
-use client::MAX_SEED;
-use kitchen::Kitchen;
-use rand::Rng;
+#[cfg(feature = "http")]
+use client::Auth;
+use kitchen::{
+    ActionObserver, DEGRADATION_RATE_IDEAL, DEGRADATION_RATE_NON_IDEAL, DegradationModel,
+    EvictionStrategy, Exponential, Kitchen, KitchenConfig, Linear, OverflowPolicy,
+    ProactiveRebalance, Summary, WaitFallback,
+};
+use model::{ChallengeClient, MAX_SEED, SolveResult};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use scheduler::{Event, PickupDistribution};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+mod action_log;
+mod analysis;
+#[cfg(feature = "http")]
 mod client;
+mod clock;
+mod diff;
 mod kitchen;
+mod lock_stats;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "test-server")]
+mod mock_server;
+mod model;
+mod output;
+mod scheduler;
+mod verify;
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Parser)]
 struct Args {
     #[arg(long, help = "Challenge server endpoint")]
-    pub endpoint: String,
+    #[cfg_attr(
+        not(feature = "test-server"),
+        arg(required_unless_present_any = ["calibrate", "diff"])
+    )]
+    pub endpoint: Option<String>,
+
+    #[cfg(feature = "test-server")]
+    #[arg(
+        long,
+        conflicts_with = "endpoint",
+        help = "Run against an in-process mock challenge server"
+    )]
+    pub mock_server: bool,
 
-    #[arg(long, help = "Authorization token (required)")]
+    #[arg(
+        long,
+        required_unless_present_any = ["calibrate", "diff"],
+        default_value_t = String::default(),
+        hide_default_value = true,
+        help = "Authorization token, or user:password with --auth-mode basic (required)"
+    )]
     pub auth: String,
 
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = AuthMode::Query,
+        help = "How the auth token is sent to the server"
+    )]
+    pub auth_mode: AuthMode,
+
     #[arg(short, long, default_value_t = String::default(), help = "Problem name (optional)")]
     pub name: String,
 
@@ -27,15 +83,26 @@ struct Args {
         long,
         default_value = "0",
         value_parser = clap::value_parser!(u64).range(0..MAX_SEED),
-        help = "Problem seed (optional)"
+        help = "Problem seed, 0 for a random one (optional)"
     )]
     pub seed: u64,
 
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with = "seed",
+        value_parser = clap::value_parser!(u64).range(0..MAX_SEED),
+        help = "Comma-separated problem seeds to run one after another (optional)"
+    )]
+    pub seeds: Vec<u64>,
+
     #[arg(
         short,
         long,
         default_value = "500",
-        help = "Inverse order rate in milliseconds"
+        allow_negative_numbers = true,
+        value_parser = parse_millis,
+        help = "Inverse order rate in milliseconds; 0 places every order at once"
     )]
     rate: u64,
 
@@ -44,76 +111,1341 @@ struct Args {
 
     #[arg(long, default_value = "8", help = "Maximum pickup time in seconds")]
     max: u64,
+
+    #[arg(
+        long,
+        allow_negative_numbers = true,
+        value_parser = parse_millis,
+        help = "Rate in milliseconds reported to the server instead of --rate (optional)"
+    )]
+    solve_rate: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Minimum pickup time in seconds reported to the server instead of --min (optional)"
+    )]
+    solve_min: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Maximum pickup time in seconds reported to the server instead of --max (optional)"
+    )]
+    solve_max: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PickupDist::Uniform,
+        help = "Distribution of pickup times within the window, drawn from the problem seed"
+    )]
+    pickup_dist: PickupDist,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = EvictionStrategy::EarliestExpiry,
+        help = "Which shelf order to discard when the kitchen is full"
+    )]
+    eviction: EvictionStrategy,
+
+    #[arg(
+        long,
+        help = "JSON file of fixed pickup delays, [{\"id\": ..., \"delay_secs\": ...}] (optional)"
+    )]
+    pickup_schedule: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "pickup_schedule",
+        help = "Only place orders and never pick them up, to load-test storage and discards"
+    )]
+    no_pickups: bool,
+
+    #[arg(
+        long,
+        help = "Run the simulation and print the solve request instead of submitting it"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Format::Text,
+        help = "Output format of the final result"
+    )]
+    format: Format,
+
+    #[arg(long, help = "Only check the endpoint and auth token, then exit")]
+    check: bool,
+
+    #[arg(
+        long,
+        help = "Only measure how late scheduled wakeups run on this machine, then exit"
+    )]
+    calibrate: bool,
+
+    #[arg(
+        long,
+        default_value = "100",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of no-op events --calibrate schedules, --rate apart"
+    )]
+    calibrate_events: u64,
+
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["BEFORE", "AFTER"],
+        help = "Only compare two action logs written by --output order by order, then exit"
+    )]
+    diff: Option<Vec<PathBuf>>,
+
+    #[arg(
+        long,
+        default_value = "100",
+        value_parser = parse_millis,
+        help = "Timing shift in milliseconds below which --diff ignores an action"
+    )]
+    diff_tolerance: u64,
+
+    #[arg(
+        long,
+        help = "JSON problem orders whose prices --diff totals improved and regressed orders by (optional)"
+    )]
+    diff_orders: Option<PathBuf>,
+
+    #[arg(
+        long,
+        default_value = "8",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of pickup worker threads"
+    )]
+    workers: u64,
+
+    #[arg(
+        long,
+        help = "Only process the first N orders of the problem (optional)"
+    )]
+    orders_limit: Option<usize>,
+
+    #[arg(
+        long,
+        conflicts_with = "seeds",
+        help = "Also write the collected actions to this JSON file (optional)"
+    )]
+    output: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = action_log::LogFormat::Json,
+        help = "Format of the --output file"
+    )]
+    output_format: action_log::LogFormat,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_parser = parse_fraction,
+        help = "Fraction of orders the customer cancels instead of picking up, chosen by the seed"
+    )]
+    cancel_fraction: f64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        value_parser = parse_fraction,
+        help = "Discard instead of handing out orders with less than this fraction of their freshness left"
+    )]
+    pickup_freshness_threshold: f64,
+
+    #[arg(
+        long,
+        value_parser = parse_fraction,
+        help = "Before each placement, move shelf orders to free ideal storage while the shelf is at least this fraction full (optional)"
+    )]
+    rebalance_high_water: Option<f64>,
+
+    #[arg(
+        long,
+        help = "Only move an order to or off the shelf when it saves more than this many dollars of freshness (optional)"
+    )]
+    move_penalty: Option<f64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Degradation::Flat,
+        help = "How much faster orders lose freshness away from their ideal temperature"
+    )]
+    degradation: Degradation,
+
+    #[arg(
+        long,
+        value_parser = parse_fraction,
+        help = "Lose this fraction of the freshness left each second instead of a fixed amount (optional)"
+    )]
+    decay: Option<f64>,
+
+    #[arg(
+        long,
+        default_value = "1",
+        help = "Seconds of freshness left at which an order decaying by --decay expires"
+    )]
+    decay_threshold: f64,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of coolers, each holding the usual number of orders"
+    )]
+    cooler_units: u64,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of heaters, each holding the usual number of orders"
+    )]
+    heater_units: u64,
+
+    #[arg(
+        long,
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Number of shelves, each holding the usual number of orders"
+    )]
+    shelf_units: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Delay in milliseconds before the first order is placed"
+    )]
+    start_delay: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Slots in an overflow rack used once the shelf is full; the challenge server \
+                doesn't know this target"
+    )]
+    overflow_size: usize,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Overflow::ForceDiscard,
+        help = "What to do with a new order when its ideal storage and the shelf are full"
+    )]
+    overflow_policy: Overflow,
+
+    #[arg(
+        long,
+        help = "With --overflow-policy wait, milliseconds to wait for a slot before falling back; \
+                defaults to how long the order stays fresh (optional)"
+    )]
+    wait_timeout: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Fallback::Reject,
+        help = "With --overflow-policy wait, what to do once no slot freed in time"
+    )]
+    wait_fallback: Fallback,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Extra milliseconds to wait after all pickups before collecting the actions"
+    )]
+    drain_delay: u64,
+
+    #[arg(
+        long,
+        help = "Run placements and pickups at exactly their scheduled times on a virtual clock, without sleeping"
+    )]
+    virtual_time: bool,
+
+    #[arg(long, help = "Gzip the solve request body")]
+    compress: bool,
+
+    #[arg(
+        long,
+        help = "Append each action to this file as a JSON line as soon as it is recorded, `-` for stdout (optional)"
+    )]
+    stream: Option<PathBuf>,
+
+    #[cfg(feature = "metrics")]
+    #[arg(
+        long,
+        help = "Serve Prometheus metrics at http://<addr>/metrics while running (optional)"
+    )]
+    metrics_addr: Option<std::net::SocketAddr>,
+}
+
+fn parse_millis(s: &str) -> Result<u64, String> {
+    s.parse()
+        .map_err(|_| format!("{s:?} is not a whole number of milliseconds, 0 or more"))
 }
 
+fn parse_fraction(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(fraction) if (0.0..=1.0).contains(&fraction) => Ok(fraction),
+        _ => Err(format!("{s:?} is not a fraction between 0 and 1")),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum PickupDist {
+    /// Whole seconds, uniformly between --min and --max
+    Uniform,
+    /// --min plus an exponential delay averaging half the window
+    Exponential,
+    /// Normal around the middle of the window, a quarter window wide
+    Normal,
+}
+
+impl PickupDist {
+    fn with_window(self, min: Duration, max: Duration) -> PickupDistribution {
+        let window = max.saturating_sub(min);
+        match self {
+            PickupDist::Uniform => PickupDistribution::Uniform { min, max },
+            PickupDist::Exponential => PickupDistribution::Exponential {
+                min,
+                max,
+                mean: window / 2,
+            },
+            PickupDist::Normal => PickupDistribution::Normal {
+                min,
+                max,
+                mean: min + window / 2,
+                std_dev: window / 4,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Degradation {
+    /// Twice as fast anywhere but the ideal temperature
+    Flat,
+    /// One step faster for each step between the storage and ideal temperatures,
+    /// so a hot order spoils faster in the cooler than on the shelf
+    Distance,
+}
+
+impl Degradation {
+    fn model(self) -> DegradationModel {
+        match self {
+            Degradation::Flat => DegradationModel::default(),
+            Degradation::Distance => DegradationModel::Distance {
+                ideal: DEGRADATION_RATE_IDEAL,
+                // one step away costs what the flat model's non-ideal rate does
+                per_step: DEGRADATION_RATE_NON_IDEAL - DEGRADATION_RATE_IDEAL,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Overflow {
+    /// Move or discard a stored order to make room
+    ForceDiscard,
+    /// Drop the new order, leaving everything stored alone
+    Reject,
+    /// Wait for a pickup or discard to free a slot, then --wait-fallback
+    Wait,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Fallback {
+    /// Drop the new order
+    Reject,
+    /// Make room after all
+    ForceDiscard,
+}
+
+impl Overflow {
+    fn policy(self, timeout: Option<Duration>, fallback: Fallback) -> OverflowPolicy {
+        match self {
+            Overflow::ForceDiscard => OverflowPolicy::ForceDiscard,
+            Overflow::Reject => OverflowPolicy::Reject,
+            Overflow::Wait => OverflowPolicy::WaitForSlot {
+                timeout,
+                fallback: match fallback {
+                    Fallback::Reject => WaitFallback::Reject,
+                    Fallback::ForceDiscard => WaitFallback::ForceDiscard,
+                },
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum AuthMode {
+    /// As the auth query parameter
+    Query,
+    /// In an Authorization: Bearer header
+    Bearer,
+    /// In an Authorization: Basic header, from --auth user:password
+    Basic,
+}
+
+#[cfg(feature = "http")]
+impl AuthMode {
+    fn with_token(self, token: &str) -> Result<Auth> {
+        Ok(match self {
+            AuthMode::Query => Auth::QueryParam(token.to_string()),
+            AuthMode::Bearer => Auth::BearerHeader(token.to_string()),
+            AuthMode::Basic => {
+                let (user, pass) = token
+                    .split_once(':')
+                    .context("--auth-mode basic needs --auth user:password")?;
+                Auth::BasicHeader(user.to_string(), pass.to_string())
+            }
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Format {
+    /// Human-readable progress and result on stdout
+    Text,
+    /// A single JSON object on stdout; progress goes to stderr
+    Json,
+}
+
+#[derive(Deserialize)]
+struct ScheduledPickup {
+    id: String,
+    delay_secs: f64,
+}
+
+fn load_pickup_schedule(path: &Path) -> Result<HashMap<String, Duration>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read pickup schedule {}", path.display()))?;
+    let entries: Vec<ScheduledPickup> = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse pickup schedule {}", path.display()))?;
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            let delay = Duration::try_from_secs_f64(entry.delay_secs)
+                .with_context(|| format!("invalid delay for order {}", entry.id))?;
+            Ok((entry.id, delay))
+        })
+        .collect()
+}
+
+fn load_prices(path: &Path) -> Result<HashMap<String, u64>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read orders {}", path.display()))?;
+    let orders: Vec<model::Order> = serde_json::from_str(&contents)
+        .with_context(|| format!("failed to parse orders {}", path.display()))?;
+    Ok(orders
+        .into_iter()
+        .map(|order| (order.id, order.price))
+        .collect())
+}
+
+/// Observer that writes every action to `path` (stdout for `-`) as one flushed JSON line.
+fn action_stream(path: &Path) -> Result<ActionObserver> {
+    let out: Box<dyn Write + Send> = if path == Path::new("-") {
+        Box::new(io::stdout())
+    } else {
+        Box::new(
+            fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open action stream {}", path.display()))?,
+        )
+    };
+    // one lock per line, so lines from different threads never interleave
+    let out = Mutex::new(out);
+
+    Ok(Box::new(move |action| {
+        let Ok(mut line) = serde_json::to_vec(action) else {
+            return;
+        };
+        line.push(b'\n');
+        let mut out = out.lock().unwrap();
+        if let Err(err) = out.write_all(&line).and_then(|_| out.flush()) {
+            eprintln!("warning: failed to stream action: {err}");
+        }
+    }))
+}
+
+// p99 wakeup drift past which --calibrate warns that runs may be unreliable
+const CALIBRATION_DRIFT_WARNING: Duration = Duration::from_millis(5);
+
 fn main() -> Result<()> {
     let args = Args::try_parse()?;
 
+    if let Some(logs) = &args.diff {
+        let before = action_log::read_actions(&logs[0])?;
+        let after = action_log::read_actions(&logs[1])?;
+        let prices = args.diff_orders.as_deref().map(load_prices).transpose()?;
+        let tolerance = Duration::from_millis(args.diff_tolerance);
+        println!(
+            "{}",
+            diff::diff_actions(&before, &after, prices.as_ref(), tolerance)
+        );
+        return Ok(());
+    }
+
+    if args.calibrate {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handler_stop = stop.clone();
+        ctrlc::set_handler(move || handler_stop.store(true, Ordering::Relaxed))
+            .context("failed to install the Ctrl-C handler")?;
+
+        let rate = Duration::from_millis(args.rate);
+        info!(
+            "Calibrating: {} events {rate:?} apart on {} workers",
+            args.calibrate_events, args.workers
+        );
+        let drift = scheduler::calibrate(
+            args.calibrate_events as usize,
+            rate,
+            args.workers as usize,
+            stop,
+        );
+        println!("Calibration: {drift}");
+        if drift.p99 > CALIBRATION_DRIFT_WARNING {
+            eprintln!(
+                "warning: p99 wakeup drift {:?} is over {CALIBRATION_DRIFT_WARNING:?}, so scheduled \
+                 runs on this machine may be unreliable; consider --virtual-time or fewer --workers",
+                drift.p99
+            );
+        }
+        return Ok(());
+    }
+
+    #[cfg(feature = "metrics")]
+    if let Some(addr) = args.metrics_addr {
+        metrics::serve(addr)?;
+        info!("Serving metrics at http://{addr}/metrics");
+    }
+    run_against_server(&args)
+}
+
+// fetches problems from the endpoint, or the mock server, and runs them
+#[cfg(feature = "http")]
+fn run_against_server(args: &Args) -> Result<()> {
+    #[cfg(feature = "test-server")]
+    let (endpoint, _mock_server) = if args.mock_server {
+        let (url, handle) = mock_server::spawn_mock_server()?;
+        (url.as_str().trim_end_matches('/').to_string(), Some(handle))
+    } else {
+        (
+            args.endpoint.clone().context("--endpoint is required")?,
+            None,
+        )
+    };
+    #[cfg(not(feature = "test-server"))]
+    let endpoint = args.endpoint.clone().context("--endpoint is required")?;
+
+    let auth = args.auth_mode.with_token(&args.auth)?;
+    let mut client = client::Client::new(&endpoint, auth).with_compression(args.compress);
+    if args.check {
+        client.health_check()?;
+        info!("Endpoint {endpoint} is reachable and accepted the auth token");
+        return Ok(());
+    }
+
+    // the first Ctrl-C stops scheduling and submits what was collected; a second one exits
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || {
+        if handler_stop.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("interrupted, finishing up with the actions collected so far");
+    })
+    .context("failed to install the Ctrl-C handler")?;
+
+    let result = run(args, &mut client, &stop)?;
+    println!("{result}");
+    Ok(())
+}
+
+#[cfg(not(feature = "http"))]
+fn run_against_server(_args: &Args) -> Result<()> {
+    bail!("built without the http feature, so only --calibrate and --diff are available")
+}
+
+/// Runs every requested problem against `client` and returns the final report.
+// without the http feature there is no ChallengeClient to drive it
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+fn run(args: &Args, client: &mut dyn ChallengeClient, stop: &Arc<AtomicBool>) -> Result<String> {
+    output::route_to_stderr(args.format == Format::Json);
+    if args.min > args.max {
+        bail!("--min {} is greater than --max {}", args.min, args.max);
+    }
+    if args.virtual_time && args.overflow_policy == Overflow::Wait {
+        // nothing else runs during a virtual-time placement to free a slot
+        bail!("--overflow-policy wait needs wall-clock scheduling, not --virtual-time");
+    }
+    if let Some(decay) = args.decay
+        && (decay == 0.0 || decay == 1.0)
+    {
+        // nothing would ever expire, or everything at once
+        bail!("--decay must be strictly between 0 and 1, not {decay}");
+    }
+
+    let pickup_schedule = match &args.pickup_schedule {
+        Some(path) => load_pickup_schedule(path)?,
+        None => HashMap::new(),
+    };
+
+    if args.seeds.is_empty() {
+        let report = run_once(args, client, args.seed, &pickup_schedule, stop)?;
+        return match args.format {
+            Format::Text => Ok(report.to_string()),
+            Format::Json => Ok(serde_json::to_string(&report)?),
+        };
+    }
+
+    // each seed is independent: report failures and keep going
+    let mut results = Vec::with_capacity(args.seeds.len());
+    for &seed in &args.seeds {
+        if stop.load(Ordering::Relaxed) {
+            eprintln!("interrupted, skipping the remaining seeds");
+            break;
+        }
+        info!("=== seed {seed} ===");
+        let result = run_once(args, client, seed, &pickup_schedule, stop);
+        match &result {
+            Ok(report) => info!("{report}"),
+            Err(err) => eprintln!("seed {seed} failed: {err:#}"),
+        }
+        results.push((seed, result));
+    }
+
+    let failures = results.iter().filter(|(_, result)| result.is_err()).count();
+    let scores: Vec<f64> = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().ok()?.result.as_ref()?.score)
+        .collect();
+    let mean_score = (!scores.is_empty()).then(|| scores.iter().sum::<f64>() / scores.len() as f64);
+    let report = match args.format {
+        Format::Text => {
+            let mut report = format!("=== {} runs ===", results.len());
+            for (seed, result) in &results {
+                let line = match result {
+                    Ok(run) => format!("seed {}: {run}", run.seed),
+                    Err(err) => format!("seed {seed}: error: {err:#}"),
+                };
+                report.push('\n');
+                report.push_str(&line);
+            }
+            if let Some(mean) = mean_score {
+                report.push_str(&format!(
+                    "\nmean score {mean:.2} over {} scored runs",
+                    scores.len()
+                ));
+            }
+            report
+        }
+        Format::Json => {
+            let runs = results
+                .iter()
+                .map(|(seed, result)| match result {
+                    Ok(run) => serde_json::to_value(run),
+                    Err(err) => Ok(json!({ "seed": seed, "error": format!("{err:#}") })),
+                })
+                .collect::<serde_json::Result<Vec<_>>>()?;
+            serde_json::to_string(&json!({ "runs": runs, "mean_score": mean_score }))?
+        }
+    };
+
+    if failures > 0 {
+        println!("{report}");
+        bail!("{failures} of {} runs failed", results.len());
+    }
+    Ok(report)
+}
+
+#[derive(Serialize)]
+struct RunOptions {
+    rate: u128, // microseconds, as submitted to the server
+    min: u128,
+    max: u128,
+}
+
+/// Outcome of a single problem.
+#[derive(Serialize)]
+struct RunReport {
+    test_id: String,
+    seed: u64, // the seed actually used, never 0
+    options: RunOptions,
+    summary: Summary,
+    throughput: Throughput,
+    result: Option<SolveResult>, // None for a dry run
+}
+
+/// How long placing and picking up a problem's orders took, wall clock.
+#[derive(Serialize)]
+struct Throughput {
+    orders: usize,
+    duration_secs: f64,
+    orders_per_sec: f64,
+    actions: usize,
+}
+
+impl Throughput {
+    fn new(orders: usize, duration: Duration, actions: usize) -> Self {
+        let duration_secs = duration.as_secs_f64();
+        Self {
+            orders,
+            duration_secs,
+            orders_per_sec: if duration_secs > 0.0 {
+                orders as f64 / duration_secs
+            } else {
+                0.0
+            },
+            actions,
+        }
+    }
+}
+
+impl fmt::Display for Throughput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "processed {} orders in {:.2} seconds ({:.1} orders/s), emitted {} actions",
+            self.orders, self.duration_secs, self.orders_per_sec, self.actions
+        )
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.result {
+            Some(result) => write!(f, "Test result: {result}"),
+            None => write!(f, "Dry run, nothing submitted"),
+        }
+    }
+}
+
+/// The kitchen `args` ask for, sized for `expected_orders`.
+fn kitchen_config(args: &Args, expected_orders: usize) -> KitchenConfig {
+    KitchenConfig {
+        eviction: args.eviction,
+        degradation: args.degradation.model(),
+        curve: match args.decay {
+            Some(decay) => Arc::new(Exponential {
+                decay,
+                threshold: args.decay_threshold,
+            }),
+            None => Arc::new(Linear),
+        },
+        overflow: args.overflow_policy.policy(
+            args.wait_timeout.map(Duration::from_millis),
+            args.wait_fallback,
+        ),
+        overflow_capacity: args.overflow_size,
+        pickup_freshness_threshold: args.pickup_freshness_threshold,
+        proactive_rebalance: args
+            .rebalance_high_water
+            .map(|high_water_mark| ProactiveRebalance { high_water_mark }),
+        move_penalty: args.move_penalty,
+        cooler_units: args.cooler_units as usize,
+        heater_units: args.heater_units as usize,
+        shelf_units: args.shelf_units as usize,
+        expected_orders,
+        // one thread on a virtual clock; the same seed should give the same ledger
+        deterministic: args.virtual_time,
+        ..KitchenConfig::default()
+    }
+}
+
+/// Fetches one problem, runs it through a fresh kitchen and submits the actions.
+fn run_once(
+    args: &Args,
+    client: &mut dyn ChallengeClient,
+    seed: u64,
+    pickup_schedule: &HashMap<String, Duration>,
+    stop: &Arc<AtomicBool>,
+) -> Result<RunReport> {
     let rate = Duration::from_millis(args.rate);
     let min = Duration::from_secs(args.min);
     let max = Duration::from_secs(args.max);
 
-    // TODO: validate min <= max
+    let model::Problem {
+        mut orders,
+        test_id,
+        seed,
+    } = client.challenge(&args.name, seed)?;
+    info!("Using seed {seed}");
+    if let Some(limit) = args.orders_limit {
+        // both the placement and pickup schedules below are built from this one list
+        orders.truncate(limit);
+    }
+    if orders.is_empty() {
+        bail!("test problem {test_id} has no orders");
+    }
+    for order in &orders {
+        order
+            .validate()
+            .with_context(|| format!("malformed test problem {test_id}"))?;
+    }
+
+    for id in pickup_schedule.keys() {
+        if !orders.iter().any(|order| &order.id == id) {
+            eprintln!("warning: pickup schedule lists unknown order {id}");
+        }
+    }
+
+    let config = kitchen_config(args, orders.len());
+
+    // without pickups every order stays, so discards are the point
+    let estimate = scheduler::estimate_peak_occupancy(orders.len(), rate, min, max, &config);
+    if !args.no_pickups && !estimate.is_feasible() {
+        eprintln!(
+            "warning: about {} orders, up to {} if pickups run late, will be stored at once \
+             but the kitchen holds {}, so some will be discarded",
+            estimate.expected, estimate.worst_case, estimate.capacity
+        );
+    }
+
+    let kitchen = Arc::new(Kitchen::with_config(config));
+    if let Some(path) = &args.stream {
+        kitchen.on_action(action_stream(path)?);
+    }
+    #[cfg(feature = "metrics")]
+    metrics::attach(&kitchen);
+
+    // computed once and shared, so a pickup is always scheduled from the exact
+    // time its placement is
+    let start_time = SystemTime::now() + Duration::from_millis(args.start_delay);
+    let placement_times: Vec<SystemTime> = (0..orders.len())
+        .map(|idx| start_time + rate * idx as u32)
+        .collect();
+    if rate.is_zero() {
+        // the kitchen's monotonic clock still gives every action its own microsecond
+        info!("Placing all {} orders at once", orders.len());
+    }
+
+    let mut pickups = Vec::with_capacity(orders.len());
+    // seeded so the same problem always cancels the same orders
+    let mut rng = StdRng::seed_from_u64(seed);
+    let distribution = args.pickup_dist.with_window(min, max);
 
-    let mut client = client::Client::new(&args.endpoint, &args.auth);
-    let (orders, test_id) = client.challenge(&args.name, args.seed)?;
+    let to_pick_up = if args.no_pickups {
+        info!("Not scheduling any pickups");
+        &[][..]
+    } else {
+        &orders[..]
+    };
+    for (order, &placement_time) in to_pick_up.iter().zip(&placement_times) {
+        let pickup_delay = match pickup_schedule.get(&order.id) {
+            Some(delay) => *delay,
+            None => distribution.sample(&mut rng),
+        };
+        let event = if rng.random_bool(args.cancel_fraction) {
+            Event::Cancel
+        } else {
+            Event::Pickup
+        };
+        pickups.push((placement_time + pickup_delay, order.id.clone(), event));
+    }
 
-    let kitchen = Arc::new(Kitchen::new());
-    let kitchen_clone = kitchen.clone();
+    let pickup_times: HashMap<String, SystemTime> = pickups
+        .iter()
+        .filter(|(.., event)| *event == Event::Pickup)
+        .map(|(at, id, _)| (id.clone(), *at))
+        .collect();
+    // the last scheduled event, which a virtual-time run reaches without waiting
+    let end_time = pickups
+        .iter()
+        .map(|(at, ..)| *at)
+        .chain(placement_times.last().copied())
+        .max()
+        .unwrap_or(start_time);
 
-    // placements
-    let orders_clone = orders.clone();
-    let placement_handle = thread::spawn(move || {
-        let start_time = SystemTime::now();
-        for (idx, order) in orders_clone.iter().enumerate() {
-            let placement_time = start_time + rate * idx as u32;
+    let scheduling_started = Instant::now();
+    let placements = placement_times.into_iter().zip(orders.clone()).collect();
+    let unfinished = if args.virtual_time {
+        // on one thread the kitchen's monotonic clock keeps every scheduled time,
+        // only nudging actions that share an instant a microsecond apart
+        scheduler::run_virtual(&kitchen, placements, pickups, stop)
+    } else {
+        scheduler::run_wall_clock(&kitchen, placements, pickups, args.workers as usize, stop)
+    };
+    let scheduling_time = scheduling_started.elapsed();
+    if unfinished > 0 {
+        eprintln!("warning: {unfinished} pickups never happened");
+    }
 
-            let now = SystemTime::now();
-            if placement_time > now {
-                let wait = placement_time.duration_since(now).unwrap();
-                thread::sleep(wait);
+    // every worker has exited and flushed its actions; this only leaves room for
+    // anything else that might still record into the kitchen
+    if args.drain_delay > 0 {
+        thread::sleep(Duration::from_millis(args.drain_delay));
+    }
+
+    let actions = kitchen.drain_actions();
+    let recorded = kitchen.action_count();
+    if recorded != actions.len() {
+        eprintln!(
+            "warning: the kitchen recorded {recorded} actions but only {} reached the ledger",
+            actions.len()
+        );
+    }
+    let mut reconciliation = Kitchen::reconcile(&orders, &actions);
+    if args.no_pickups {
+        // nothing was ever going to pick these up
+        reconciliation.unresolved.clear();
+    }
+    if !reconciliation.is_clean() {
+        eprintln!("warning: orders unaccounted for: {reconciliation}");
+        let now = end_time.max(SystemTime::now());
+        for id in &reconciliation.unresolved {
+            if let Some(inspection) = kitchen.inspect(id, now) {
+                let expiry = match inspection.expires_at.duration_since(now) {
+                    Ok(left) => format!("expiring in {left:.1?}"),
+                    Err(_) => "expired".to_string(),
+                };
+                eprintln!("  still stored: {inspection}, {expiry}");
             }
+        }
+    }
 
-            kitchen_clone.place_order(order.clone(), SystemTime::now());
+    let summary = kitchen.action_counts();
+    if stop.load(Ordering::Relaxed) {
+        eprintln!(
+            "warning: run interrupted, only {} actions were collected",
+            actions.len()
+        );
+    }
+    info!("Summary: {summary}");
+    info!("Pickups: {}", kitchen.pickup_stats());
+    info!("Placements: {}", kitchen.placement_latency_stats());
+    let throughput = Throughput::new(orders.len(), scheduling_time, actions.len());
+    info!("Throughput: {throughput}");
+    #[cfg(feature = "metrics")]
+    info!("Lock stats: {:?}", kitchen.lock_stats());
+
+    if let Err(errors) = verify::validate_actions(&actions, &orders, kitchen.config()) {
+        for error in &errors {
+            eprintln!("invalid action {error}");
         }
-    });
+        bail!("refusing to submit, {} invariant violations", errors.len());
+    }
+    if !args.no_pickups {
+        let review =
+            analysis::review_capacity_discards(kitchen.config(), &orders, &actions, &pickup_times);
+        info!("Discards: {review}");
+    }
 
-    let orders_clone = orders.clone();
-    let mut pickup_handles = Vec::new();
-    let start_time = SystemTime::now();
+    if let Some(path) = &args.output {
+        action_log::write_actions(path, &actions, args.output_format)?;
+        info!("Wrote {} actions to {}", actions.len(), path.display());
+    }
 
-    for (idx, order) in orders_clone.iter().enumerate() {
-        let kitchen_pickup = kitchen.clone();
-        let order_id = order.id.clone();
+    // the options the server scores against, which only differ from the
+    // schedule above when overridden for an experiment
+    let scheduled = (rate, min, max);
+    let (rate, min, max) = (
+        args.solve_rate.map_or(rate, Duration::from_millis),
+        args.solve_min.map_or(min, Duration::from_secs),
+        args.solve_max.map_or(max, Duration::from_secs),
+    );
+    if (rate, min, max) != scheduled {
+        eprintln!(
+            "warning: reporting options that differ from the schedule, the server may reject the solution"
+        );
+    }
+    let result = if args.dry_run {
+        let body = model::solve_body(rate, min, max, &actions);
+        info!("Dry run, not submitting. Solve request body for test {test_id}:");
+        info!("{}", serde_json::to_string_pretty(&body)?);
+        None
+    } else {
+        Some(client.solve(&test_id, rate, min, max, &actions)?)
+    };
 
-        let placement_time = start_time + rate * idx as u32;
+    Ok(RunReport {
+        test_id,
+        seed,
+        options: RunOptions {
+            rate: rate.as_micros(),
+            min: min.as_micros(),
+            max: max.as_micros(),
+        },
+        summary,
+        throughput,
+        result,
+    })
+}
 
-        let pickup_delay = rand::rng().random_range(min.as_secs()..=max.as_secs());
-        let pickup_time = placement_time + Duration::from_secs(pickup_delay);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::{Action, ActionKind, Location, Order, Problem, Temp};
 
-        let handle = thread::spawn(move || {
-            let now = SystemTime::now();
-            if pickup_time > now {
-                let wait = pickup_time.duration_since(now).unwrap();
-                thread::sleep(wait);
-            }
-            kitchen_pickup.pickup_order(&order_id, SystemTime::now());
-        });
+    // serves canned orders and keeps what is submitted for them
+    #[derive(Default)]
+    struct MockClient {
+        orders: Vec<Order>,
+        challenges: usize,
+        submitted: Vec<(String, Duration, Duration, Duration, Vec<Action>)>,
+    }
+
+    impl ChallengeClient for MockClient {
+        fn challenge(&mut self, _name: &str, seed: u64) -> Result<Problem> {
+            self.challenges += 1;
+            Ok(Problem {
+                orders: self.orders.clone(),
+                test_id: format!("test-{}", self.challenges),
+                seed: seed.max(1),
+            })
+        }
 
-        pickup_handles.push(handle);
+        fn solve(
+            &mut self,
+            test_id: &str,
+            rate: Duration,
+            min: Duration,
+            max: Duration,
+            actions: &[Action],
+        ) -> Result<SolveResult> {
+            self.submitted
+                .push((test_id.to_string(), rate, min, max, actions.to_vec()));
+            Ok(SolveResult::parse(r#"{"score": 1.5}"#.to_string()))
+        }
+    }
+
+    fn mock_client(count: usize) -> MockClient {
+        let orders = (0..count)
+            .map(|idx| Order {
+                id: format!("order-{idx}"),
+                name: format!("Order {idx}"),
+                temp: [Temp::Hot, Temp::Cold, Temp::Room][idx % 3],
+                price: 10,
+                freshness: 60,
+                deadline_secs: None,
+            })
+            .collect();
+        MockClient {
+            orders,
+            ..MockClient::default()
+        }
     }
 
-    placement_handle.join().unwrap();
-    for handle in pickup_handles {
-        handle.join().unwrap();
+    fn args(extra: &[&str]) -> Args {
+        let base = [
+            "challenge",
+            "--endpoint",
+            "http://unused",
+            "--auth",
+            "token",
+        ];
+        Args::try_parse_from(base.iter().chain(extra)).unwrap()
     }
 
-    thread::sleep(Duration::from_millis(100)); // give it a bit extra
+    fn no_stop() -> Arc<AtomicBool> {
+        Arc::new(AtomicBool::new(false))
+    }
 
-    let actions = kitchen.get_actions();
+    #[test]
+    fn run_places_resolves_and_submits_every_order() {
+        let mut client = mock_client(20);
+        let args = args(&[
+            "--seed",
+            "7",
+            "--virtual-time",
+            "--rate",
+            "100",
+            "--min",
+            "1",
+            "--max",
+            "2",
+        ]);
 
-    let result = client.solve(&test_id, rate, min, max, &actions)?;
+        let report = run(&args, &mut client, &no_stop()).unwrap();
+        assert_eq!(report, r#"Test result: {"score": 1.5}"#);
 
-    println!("Test result: {result}");
-    Ok(())
+        let [(test_id, rate, min, max, actions)] = &client.submitted[..] else {
+            panic!("expected one submission, got {}", client.submitted.len());
+        };
+        assert_eq!(test_id, "test-1");
+        assert_eq!(
+            (*rate, *min, *max),
+            (
+                Duration::from_millis(100),
+                Duration::from_secs(1),
+                Duration::from_secs(2)
+            )
+        );
+        let config = KitchenConfig::default();
+        assert_eq!(
+            verify::validate_actions(actions, &client.orders, &config),
+            Ok(())
+        );
+        for order in &client.orders {
+            let kinds: Vec<ActionKind> = actions
+                .iter()
+                .filter(|action| action.id == order.id)
+                .map(|action| action.action)
+                .collect();
+            assert_eq!(kinds.first(), Some(&ActionKind::Place), "{}", order.id);
+            assert!(
+                matches!(kinds.last(), Some(ActionKind::Pickup | ActionKind::Discard)),
+                "{} ends with {kinds:?}",
+                order.id
+            );
+        }
+    }
+
+    #[cfg(feature = "test-server")]
+    #[test]
+    fn runs_end_to_end_against_the_mock_server() {
+        let (url, _server) = mock_server::spawn_mock_server().unwrap();
+        let endpoint = url.as_str().trim_end_matches('/');
+        let mut client = client::Client::new(endpoint, client::Auth::QueryParam("token".into()))
+            .with_compression(true);
+        let args = args(&["--seed", "5", "--virtual-time", "--rate", "0"]);
+
+        let report = run(&args, &mut client, &no_stop()).unwrap();
+        assert!(
+            report.starts_with("Test result: pass: mock score 100 for test mock-5 ("),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn dry_run_submits_nothing() {
+        let mut client = mock_client(5);
+        let args = args(&["--virtual-time", "--rate", "0", "--dry-run"]);
+        let report = run(&args, &mut client, &no_stop()).unwrap();
+        assert_eq!(report, "Dry run, nothing submitted");
+        assert_eq!(client.challenges, 1);
+        assert!(client.submitted.is_empty());
+    }
+
+    #[test]
+    fn seeds_run_one_problem_each() {
+        let mut client = mock_client(5);
+        let args = args(&[
+            "--seeds",
+            "1,2,3",
+            "--virtual-time",
+            "--rate",
+            "0",
+            "--format",
+            "json",
+        ]);
+        let report: serde_json::Value =
+            serde_json::from_str(&run(&args, &mut client, &no_stop()).unwrap()).unwrap();
+        assert_eq!(report["runs"].as_array().unwrap().len(), 3);
+        assert_eq!(report["mean_score"], 1.5);
+        assert_eq!(client.submitted.len(), 3);
+    }
+
+    #[test]
+    fn reject_policy_drops_orders_instead_of_discarding() {
+        let mut client = mock_client(30);
+        for order in &mut client.orders {
+            order.temp = Temp::Hot;
+        }
+        let args = args(&[
+            "--virtual-time",
+            "--rate",
+            "0",
+            "--min",
+            "10",
+            "--max",
+            "10",
+            "--overflow-policy",
+            "reject",
+        ]);
+        run(&args, &mut client, &no_stop()).unwrap();
+
+        let actions = &client.submitted[0].4;
+        let count = |kind| {
+            actions
+                .iter()
+                .filter(|action| action.action == kind)
+                .count()
+        };
+        // the heater and the shelf fill up, everything after them is dropped
+        assert_eq!(count(ActionKind::Place), 18);
+        assert_eq!(count(ActionKind::Pickup), 18);
+        assert_eq!(count(ActionKind::Discard), 0);
+    }
+
+    #[test]
+    fn wait_policy_needs_wall_clock_scheduling() {
+        let mut client = mock_client(5);
+        let args = args(&["--virtual-time", "--overflow-policy", "wait"]);
+        assert!(run(&args, &mut client, &no_stop()).is_err());
+        assert_eq!(client.challenges, 0);
+    }
+
+    #[test]
+    fn virtual_time_runs_of_one_seed_record_the_same_ledger() {
+        let mut client = mock_client(40);
+        let args = args(&["--seed", "9", "--virtual-time", "--rate", "0", "--min", "1"]);
+        run(&args, &mut client, &no_stop()).unwrap();
+        run(&args, &mut client, &no_stop()).unwrap();
+
+        let relative = |actions: &[Action]| {
+            let start = actions[0].timestamp;
+            actions
+                .iter()
+                .map(|action| (action.timestamp - start, action.id.clone(), action.action))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            relative(&client.submitted[0].4),
+            relative(&client.submitted[1].4)
+        );
+    }
+
+    #[test]
+    fn wall_clock_pickups_wait_for_their_placement() {
+        // every pickup is due the moment its order is placed
+        let mut client = mock_client(30);
+        let args = args(&["--rate", "0", "--min", "0", "--max", "0", "--workers", "4"]);
+        run(&args, &mut client, &no_stop()).unwrap();
+
+        let actions = &client.submitted[0].4;
+        let config = KitchenConfig::default();
+        assert_eq!(
+            verify::validate_actions(actions, &client.orders, &config),
+            Ok(())
+        );
+        let resolved = actions
+            .iter()
+            .filter(|action| matches!(action.action, ActionKind::Pickup | ActionKind::Discard))
+            .count();
+        assert_eq!(resolved, client.orders.len());
+    }
+
+    #[test]
+    fn min_above_max_is_rejected() {
+        let mut client = mock_client(5);
+        let args = args(&["--min", "9", "--max", "3"]);
+        assert!(run(&args, &mut client, &no_stop()).is_err());
+        assert_eq!(client.challenges, 0);
+    }
+
+    #[test]
+    fn zero_rate_places_a_burst_at_distinct_instants() {
+        let mut client = mock_client(30);
+        let args = args(&["--rate", "0", "--min", "0", "--max", "0"]);
+        run(&args, &mut client, &no_stop()).unwrap();
+
+        let actions = &client.submitted[0].4;
+        let placements = actions
+            .iter()
+            .filter(|action| action.action == ActionKind::Place)
+            .count();
+        assert_eq!(placements, 30);
+        assert!(
+            actions
+                .windows(2)
+                .all(|pair| pair[0].timestamp < pair[1].timestamp),
+            "timestamps repeat or go backwards"
+        );
+        let config = KitchenConfig::default();
+        assert_eq!(
+            verify::validate_actions(actions, &client.orders, &config),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn negative_rates_are_rejected() {
+        let base = [
+            "challenge",
+            "--endpoint",
+            "http://unused",
+            "--auth",
+            "token",
+        ];
+        for flag in ["--rate", "--solve-rate"] {
+            let parsed = Args::try_parse_from(base.iter().chain(&[flag, "-5"]));
+            let error = parsed.err().expect(flag).to_string();
+            assert!(error.contains("0 or more"), "{flag}: {error}");
+        }
+    }
+
+    #[test]
+    fn distance_degradation_runs_validate_against_its_rates() {
+        let mut client = mock_client(20);
+        let distance = args(&[
+            "--virtual-time",
+            "--rate",
+            "100",
+            "--min",
+            "1",
+            "--max",
+            "30",
+            "--degradation",
+            "distance",
+        ]);
+        run(&distance, &mut client, &no_stop()).unwrap();
+        assert_eq!(client.submitted.len(), 1);
+
+        let config = kitchen_config(&distance, 20);
+        assert_eq!(
+            config.degradation,
+            DegradationModel::Distance {
+                ideal: DEGRADATION_RATE_IDEAL,
+                per_step: DEGRADATION_RATE_NON_IDEAL - DEGRADATION_RATE_IDEAL,
+            }
+        );
+        // two steps from hot, the cooler spoils a hot order faster than the shelf
+        assert!(config.rate(Temp::Hot, Location::Cooler) > config.rate(Temp::Hot, Location::Shelf));
+        let flat = kitchen_config(&args(&[]), 20);
+        assert_eq!(flat.degradation, DegradationModel::default());
+        assert_eq!(
+            flat.rate(Temp::Hot, Location::Cooler),
+            flat.rate(Temp::Hot, Location::Shelf)
+        );
+    }
+
+    #[test]
+    fn decaying_runs_validate_against_their_curve() {
+        let mut client = mock_client(20);
+        let decaying = args(&[
+            "--virtual-time",
+            "--rate",
+            "100",
+            "--min",
+            "1",
+            "--max",
+            "30",
+            "--decay",
+            "0.05",
+        ]);
+        run(&decaying, &mut client, &no_stop()).unwrap();
+        assert_eq!(client.submitted.len(), 1);
+
+        // 5% of what's left goes every second, instead of a second per second
+        let ten_seconds = Duration::from_secs(10);
+        let curve = kitchen_config(&decaying, 20).curve;
+        let remaining = curve.remaining(100.0, ten_seconds, 1.0);
+        assert!(
+            (remaining - 100.0 * 0.95_f64.powi(10)).abs() < 1e-9,
+            "{remaining}"
+        );
+        assert_eq!(curve.threshold(), 1.0);
+        let linear = kitchen_config(&args(&[]), 20).curve;
+        assert_eq!(linear.remaining(100.0, ten_seconds, 1.0), 90.0);
+        assert_eq!(linear.threshold(), 0.0);
+        let threshold = args(&["--decay", "0.05", "--decay-threshold", "2.5"]);
+        assert_eq!(kitchen_config(&threshold, 20).curve.threshold(), 2.5);
+
+        let mut client = mock_client(5);
+        let instant = args(&["--decay", "1"]);
+        assert!(run(&instant, &mut client, &no_stop()).is_err());
+        assert_eq!(client.challenges, 0);
+    }
 }
@@ -0,0 +1,246 @@
+//! Prometheus exporter for `--metrics-addr`, built only with the `metrics` feature.
+//!
+//! Serves `GET /metrics` in the Prometheus text format: action counters summed
+//! over every run since startup, plus occupancy and pickup freshness of the
+//! kitchen currently running. One request per connection, like the mock server.
+
+use crate::kitchen::Kitchen;
+use crate::model::{ActionKind, Location};
+
+use anyhow::{Context, Result};
+
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::thread;
+
+static METRICS: OnceLock<Arc<Metrics>> = OnceLock::new();
+
+#[derive(Default)]
+struct Metrics {
+    placed: AtomicU64,
+    moved: AtomicU64,
+    picked_up: AtomicU64,
+    discarded: AtomicU64,
+    // weak, since the kitchen's observer holds the metrics
+    kitchen: Mutex<Weak<Kitchen>>,
+}
+
+/// Starts serving `/metrics` on `addr` from a background thread.
+pub fn serve(addr: SocketAddr) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("failed to bind metrics server {addr}"))?;
+    let metrics = METRICS.get_or_init(Arc::default).clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if let Err(err) = handle(stream, &metrics) {
+                eprintln!("metrics server: {err:#}");
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Counts `kitchen`'s actions and reports its occupancy from now on. Does
+/// nothing unless [`serve`] was called.
+pub fn attach(kitchen: &Arc<Kitchen>) {
+    if let Some(metrics) = METRICS.get() {
+        attach_to(metrics, kitchen);
+    }
+}
+
+fn attach_to(metrics: &Arc<Metrics>, kitchen: &Arc<Kitchen>) {
+    *metrics.kitchen.lock().unwrap() = Arc::downgrade(kitchen);
+
+    let metrics = metrics.clone();
+    kitchen.on_action(Box::new(move |action| {
+        let counter = match action.action {
+            ActionKind::Place => &metrics.placed,
+            ActionKind::Move => &metrics.moved,
+            ActionKind::Pickup => &metrics.picked_up,
+            ActionKind::Discard => &metrics.discarded,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }));
+}
+
+fn handle(mut stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+
+    let (status, body) = if path == "/metrics" {
+        ("200 OK", render(metrics))
+    } else {
+        ("404 Not Found", "not found\n".to_string())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nConnection: close\r\n\
+         Content-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+fn render(metrics: &Metrics) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# HELP kitchen_actions_total Actions recorded, by type."
+    );
+    let _ = writeln!(out, "# TYPE kitchen_actions_total counter");
+    for (action, counter) in [
+        (ActionKind::Place, &metrics.placed),
+        (ActionKind::Move, &metrics.moved),
+        (ActionKind::Pickup, &metrics.picked_up),
+        (ActionKind::Discard, &metrics.discarded),
+    ] {
+        let _ = writeln!(
+            out,
+            "kitchen_actions_total{{action=\"{action}\"}} {}",
+            counter.load(Ordering::Relaxed)
+        );
+    }
+
+    let Some(kitchen) = metrics.kitchen.lock().unwrap().upgrade() else {
+        return out;
+    };
+
+    let _ = writeln!(
+        out,
+        "# HELP kitchen_occupancy Orders currently stored, by location."
+    );
+    let _ = writeln!(out, "# TYPE kitchen_occupancy gauge");
+    for location in Location::ALL {
+        let _ = writeln!(
+            out,
+            "kitchen_occupancy{{location=\"{location}\"}} {}",
+            kitchen.occupancy(location)
+        );
+    }
+
+    let stats = kitchen.pickup_stats();
+    let _ = writeln!(
+        out,
+        "# HELP kitchen_pickup_freshness Fraction of freshness left on orders at pickup."
+    );
+    let _ = writeln!(out, "# TYPE kitchen_pickup_freshness histogram");
+    let mut cumulative = 0;
+    for (bucket, count) in stats.histogram.iter().enumerate() {
+        cumulative += count;
+        let upper = (bucket + 1) as f64 / stats.histogram.len() as f64;
+        let _ = writeln!(
+            out,
+            "kitchen_pickup_freshness_bucket{{le=\"{upper:.1}\"}} {cumulative}"
+        );
+    }
+    let _ = writeln!(
+        out,
+        "kitchen_pickup_freshness_bucket{{le=\"+Inf\"}} {}",
+        stats.count
+    );
+    let _ = writeln!(
+        out,
+        "kitchen_pickup_freshness_sum {}",
+        stats.mean * stats.count as f64
+    );
+    let _ = writeln!(out, "kitchen_pickup_freshness_count {}", stats.count);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{Order, Temp};
+
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs)
+    }
+
+    fn order(id: &str) -> Order {
+        Order {
+            id: id.to_string(),
+            name: id.to_string(),
+            temp: Temp::Room,
+            price: 10,
+            freshness: 100,
+            deadline_secs: None,
+        }
+    }
+
+    #[test]
+    fn render_reports_counters_occupancy_and_a_cumulative_histogram() {
+        let metrics = Arc::new(Metrics::default());
+        let kitchen = Arc::new(Kitchen::new());
+        attach_to(&metrics, &kitchen);
+        for id in ["a", "b", "c"] {
+            kitchen.place_order(order(id), at(0));
+        }
+        // a quarter of a's freshness gone, and 95% of b's
+        kitchen.pickup_order("a", at(25));
+        kitchen.pickup_order("b", at(95));
+
+        let text = render(&metrics);
+        let lines: Vec<&str> = text.lines().collect();
+        for line in [
+            "# TYPE kitchen_actions_total counter",
+            "kitchen_actions_total{action=\"place\"} 3",
+            "kitchen_actions_total{action=\"move\"} 0",
+            "kitchen_actions_total{action=\"pickup\"} 2",
+            "kitchen_actions_total{action=\"discard\"} 0",
+            "# TYPE kitchen_occupancy gauge",
+            "kitchen_occupancy{location=\"shelf\"} 1",
+            "kitchen_occupancy{location=\"heater\"} 0",
+            "# TYPE kitchen_pickup_freshness histogram",
+            "kitchen_pickup_freshness_bucket{le=\"0.1\"} 1",
+            "kitchen_pickup_freshness_bucket{le=\"0.7\"} 1",
+            "kitchen_pickup_freshness_bucket{le=\"0.8\"} 2",
+            "kitchen_pickup_freshness_bucket{le=\"1.0\"} 2",
+            "kitchen_pickup_freshness_bucket{le=\"+Inf\"} 2",
+            "kitchen_pickup_freshness_count 2",
+        ] {
+            assert!(lines.contains(&line), "missing {line:?} in\n{text}");
+        }
+        let buckets = lines
+            .iter()
+            .filter(|line| line.starts_with("kitchen_pickup_freshness_bucket"))
+            .count();
+        assert_eq!(buckets, 11);
+        let sum: f64 = lines
+            .iter()
+            .find_map(|line| line.strip_prefix("kitchen_pickup_freshness_sum "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((sum - 0.8).abs() < 1e-9, "{sum}");
+    }
+
+    #[test]
+    fn render_without_a_kitchen_reports_only_counters() {
+        let metrics = Arc::new(Metrics::default());
+        let text = render(&metrics);
+        assert!(text.contains("kitchen_actions_total{action=\"place\"} 0"));
+        assert!(!text.contains("kitchen_occupancy"));
+        assert!(!text.contains("kitchen_pickup_freshness"));
+
+        // the counters outlive the kitchen they counted
+        let kitchen = Arc::new(Kitchen::new());
+        attach_to(&metrics, &kitchen);
+        kitchen.place_order(order("a"), at(0));
+        drop(kitchen);
+        let text = render(&metrics);
+        assert!(text.contains("kitchen_actions_total{action=\"place\"} 1"));
+        assert!(!text.contains("kitchen_occupancy"));
+    }
+}
@@ -0,0 +1,234 @@
+//! In-process stand-in for the challenge server, for exercising the real
+//! `Client` and the whole harness without network access or a token.
+//!
+//! Serves `GET/HEAD /interview/challenge/new` with a deterministic problem for
+//! the requested seed and accepts `POST /interview/challenge/solve`, answering
+//! with a canned result. One request per connection, which is all reqwest
+//! needs when the response says `Connection: close`.
+
+use anyhow::{Context, Result, anyhow};
+use flate2::read::GzDecoder;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use reqwest::Url;
+use serde_json::{Value, json};
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+
+const MOCK_ORDERS: usize = 40;
+const MOCK_NAMES: [&str; 6] = [
+    "Cheese Pizza",
+    "Iced Tea",
+    "Banana Split",
+    "Burrito",
+    "Caesar Salad",
+    "Ramen",
+];
+const MOCK_TEMPS: [&str; 3] = ["hot", "cold", "room"];
+
+/// Keeps the mock server running; dropping it shuts the server down.
+pub struct ServerHandle {
+    addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for ServerHandle {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::Release);
+        // wake the accept loop so it notices the flag
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Starts a mock challenge server on an ephemeral localhost port and returns
+/// the endpoint to hand to `Client::new`.
+pub fn spawn_mock_server() -> Result<(Url, ServerHandle)> {
+    let listener = TcpListener::bind("127.0.0.1:0").context("failed to bind mock server")?;
+    let addr = listener.local_addr()?;
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let flag = shutdown.clone();
+    let thread = thread::spawn(move || {
+        for stream in listener.incoming() {
+            if flag.load(Ordering::Acquire) {
+                break;
+            }
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if let Err(err) = handle(stream) {
+                eprintln!("mock server: {err:#}");
+            }
+        }
+    });
+
+    let url = Url::parse(&format!("http://{addr}"))?;
+    Ok((
+        url,
+        ServerHandle {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        },
+    ))
+}
+
+/// The problem the mock server hands out for `seed`.
+pub fn mock_orders(seed: u64) -> Value {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let orders: Vec<Value> = (0..MOCK_ORDERS)
+        .map(|idx| {
+            json!({
+                "id": format!("{:05x}", (seed as usize).wrapping_add(idx) & 0xfffff),
+                "name": MOCK_NAMES[rng.random_range(0..MOCK_NAMES.len())],
+                "temp": MOCK_TEMPS[rng.random_range(0..MOCK_TEMPS.len())],
+                "price": rng.random_range(1..=30),
+                "freshness": rng.random_range(10..=300),
+            })
+        })
+        .collect();
+    Value::Array(orders)
+}
+
+struct Request {
+    method: String,
+    url: Url,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn query(&self, key: &str) -> Option<String> {
+        self.url
+            .query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+    }
+}
+
+fn read_request(stream: &TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| anyhow!("empty request"))?
+        .to_string();
+    let target = parts
+        .next()
+        .ok_or_else(|| anyhow!("missing request target"))?;
+    let url = Url::parse("http://mock")?.join(target)?;
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length = headers
+        .get("content-length")
+        .map(|v| v.parse::<usize>())
+        .transpose()?
+        .unwrap_or(0);
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        url,
+        headers,
+        body,
+    })
+}
+
+fn handle(mut stream: TcpStream) -> Result<()> {
+    let request = read_request(&stream)?;
+
+    let (status, headers, body) = route(&request);
+    let mut response = format!("HTTP/1.1 {status}\r\nConnection: close\r\n");
+    for (name, value) in &headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+    stream.write_all(response.as_bytes())?;
+    if request.method != "HEAD" {
+        stream.write_all(body.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn route(request: &Request) -> (&'static str, Vec<(&'static str, String)>, String) {
+    // any of the client's auth mechanisms will do
+    let authorized = request.query("auth").is_some_and(|auth| !auth.is_empty())
+        || request.headers.contains_key("authorization");
+    if !authorized {
+        return ("401 Unauthorized", vec![], "missing auth".to_string());
+    }
+
+    match (request.method.as_str(), request.url.path()) {
+        ("GET" | "HEAD", "/interview/challenge/new") => {
+            let seed = request
+                .query("seed")
+                .and_then(|seed| seed.parse().ok())
+                .unwrap_or(0);
+            (
+                "200 OK",
+                vec![
+                    ("x-test-id", format!("mock-{seed}")),
+                    ("Content-Type", "application/json".to_string()),
+                ],
+                mock_orders(seed).to_string(),
+            )
+        }
+        ("POST", "/interview/challenge/solve") => {
+            let Some(test_id) = request.headers.get("x-test-id") else {
+                return ("400 Bad Request", vec![], "missing x-test-id".to_string());
+            };
+            let mut decoded = Vec::new();
+            let raw = if request
+                .headers
+                .get("content-encoding")
+                .is_some_and(|encoding| encoding == "gzip")
+            {
+                if GzDecoder::new(request.body.as_slice())
+                    .read_to_end(&mut decoded)
+                    .is_err()
+                {
+                    return ("400 Bad Request", vec![], "invalid gzip".to_string());
+                }
+                &decoded
+            } else {
+                &request.body
+            };
+            let Ok(body) = serde_json::from_slice::<Value>(raw) else {
+                return ("400 Bad Request", vec![], "invalid json".to_string());
+            };
+            let actions = body["actions"].as_array().map_or(0, Vec::len);
+            (
+                "200 OK",
+                vec![("x-test-id", test_id.clone())],
+                format!("pass: mock score 100 for test {test_id} ({actions} actions)"),
+            )
+        }
+        _ => ("404 Not Found", vec![], "not found".to_string()),
+    }
+}
@@ -0,0 +1,461 @@
+//! The challenge's data model: orders, the actions taken on them and the
+//! server operations the harness drives, free of any HTTP client so the
+//! kitchen builds without networking.
+
+use anyhow::Result;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const MAX_SEED: u64 = 1 << 63;
+
+/// What happened to an order; serialized in lowercase as the server expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionKind {
+    Place,
+    Move,
+    Pickup,
+    Discard,
+}
+
+impl ActionKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ActionKind::Place => "place",
+            ActionKind::Move => "move",
+            ActionKind::Pickup => "pickup",
+            ActionKind::Discard => "discard",
+        }
+    }
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ActionKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [
+            ActionKind::Place,
+            ActionKind::Move,
+            ActionKind::Pickup,
+            ActionKind::Discard,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == s)
+        .ok_or_else(|| anyhow!("unknown action {s:?}"))
+    }
+}
+
+/// Where an order is stored; serialized in lowercase as the server expects.
+/// The overflow rack isn't part of the challenge, so the server rejects it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Location {
+    Cooler,
+    Heater,
+    Shelf,
+    Overflow,
+}
+
+impl Location {
+    /// Every location, in the kitchen's lock order.
+    pub const ALL: [Location; 4] = [
+        Location::Cooler,
+        Location::Heater,
+        Location::Shelf,
+        Location::Overflow,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Location::Cooler => "cooler",
+            Location::Heater => "heater",
+            Location::Shelf => "shelf",
+            Location::Overflow => "overflow",
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Location {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Location::ALL
+            .into_iter()
+            .find(|location| location.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown location {s:?}"))
+    }
+}
+
+/// One storage unit of a location, e.g. the second of several heaters. The
+/// first unit goes by the location's plain name, so a kitchen with a single
+/// unit of each location names them as the server expects; later ones are
+/// numbered from 2 (`heater-2`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Unit {
+    pub location: Location,
+    pub index: usize,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            0 => write!(f, "{}", self.location),
+            index => write!(f, "{}-{}", self.location, index + 1),
+        }
+    }
+}
+
+impl FromStr for Unit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (location, index) = match s.rsplit_once('-') {
+            Some((location, number)) => {
+                let number: usize = number
+                    .parse()
+                    .ok()
+                    .filter(|&number| number >= 2)
+                    .ok_or_else(|| anyhow!("unknown unit {s:?}"))?;
+                (location, number - 1)
+            }
+            None => (s, 0),
+        };
+        Ok(Unit {
+            location: location.parse()?,
+            index,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Action {
+    pub timestamp: u64, // unix timestamp in microseconds
+    pub id: String,
+    pub action: ActionKind,
+    pub target: Location,
+    pub unit: usize, // which unit of the target; submitted as part of the target
+    pub seq: u64,    // order in which the kitchen recorded the action; not submitted
+    pub reason: Option<DiscardReason>, // why a discard happened; not submitted
+    pub group: u64,  // kitchen operation that recorded the action, 0 if none; not submitted
+}
+
+impl Action {
+    pub fn new(id: &str, action: ActionKind, target: Location, timestamp: SystemTime) -> Self {
+        Self {
+            action,
+            id: id.to_string(),
+            target,
+            timestamp: timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_micros()
+                .try_into()
+                .unwrap(),
+            unit: 0,
+            seq: 0,
+            reason: None,
+            group: 0,
+        }
+    }
+
+    pub fn target_unit(&self) -> Unit {
+        Unit {
+            location: self.target,
+            index: self.unit,
+        }
+    }
+}
+
+impl Serialize for Action {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut action = serializer.serialize_struct("Action", 4)?;
+        action.serialize_field("timestamp", &self.timestamp)?;
+        action.serialize_field("id", &self.id)?;
+        action.serialize_field("action", &self.action)?;
+        action.serialize_field("target", &self.target_unit().to_string())?;
+        action.end()
+    }
+}
+
+// reads back what Serialize writes; the fields it leaves out start at their defaults
+impl<'de> Deserialize<'de> for Action {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Wire {
+            timestamp: u64,
+            id: String,
+            action: ActionKind,
+            target: String,
+        }
+
+        let wire = Wire::deserialize(deserializer)?;
+        let target: Unit = wire.target.parse().map_err(serde::de::Error::custom)?;
+        Ok(Action {
+            timestamp: wire.timestamp,
+            id: wire.id,
+            action: wire.action,
+            target: target.location,
+            unit: target.index,
+            seq: 0,
+            reason: None,
+            group: 0,
+        })
+    }
+}
+
+/// Why the kitchen discarded an order. All of them are a `discard` on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiscardReason {
+    /// Found expired while making room, although it was only ever stored at its
+    /// ideal temperature.
+    ExpiredIdeal,
+    /// Found expired while making room, after spending time outside its ideal temperature.
+    ExpiredNonIdeal,
+    /// Had expired by the time its pickup arrived.
+    Missed,
+    /// Still fresh, but picked up after the order's deadline.
+    Late,
+    /// Evicted from the full shelf to make room for a new order.
+    Capacity,
+    /// Cancelled by the customer.
+    Cancelled,
+    /// Still fresh at pickup, but below the kitchen's pickup freshness threshold.
+    Stale,
+}
+
+impl fmt::Display for DiscardReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            DiscardReason::ExpiredIdeal => "expired in ideal storage",
+            DiscardReason::ExpiredNonIdeal => "expired after non-ideal storage",
+            DiscardReason::Missed => "expired before pickup",
+            DiscardReason::Late => "picked up past its deadline",
+            DiscardReason::Capacity => "evicted for capacity",
+            DiscardReason::Cancelled => "cancelled",
+            DiscardReason::Stale => "too stale to hand out",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Ideal storage temperature of an order. `Any` keeps equally well everywhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Temp {
+    Hot,
+    Cold,
+    Room,
+    Any,
+}
+
+impl Temp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Temp::Hot => "hot",
+            Temp::Cold => "cold",
+            Temp::Room => "room",
+            Temp::Any => "any",
+        }
+    }
+}
+
+impl fmt::Display for Temp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Temp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        [Temp::Hot, Temp::Cold, Temp::Room, Temp::Any]
+            .into_iter()
+            .find(|temp| temp.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown temperature {s:?}"))
+    }
+}
+
+impl TryFrom<String> for Temp {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Order {
+    pub id: String,
+    pub name: String,
+    pub temp: Temp,
+    #[serde(default)]
+    pub price: u64,
+    pub freshness: u64, // in seconds
+    // seconds after placement by which the customer must pick the order up, fresh or not
+    #[serde(default)]
+    pub deadline_secs: Option<u64>,
+}
+
+impl Order {
+    /// Checks the fields the kitchen relies on, so malformed problems fail up front.
+    pub fn validate(&self) -> Result<()> {
+        if self.id.is_empty() {
+            return Err(anyhow!("order {:?} has an empty id", self.name));
+        }
+        Ok(())
+    }
+}
+
+/// The server's verdict on a submitted solution.
+#[derive(Debug, Clone, Serialize)]
+pub struct SolveResult {
+    /// Numeric score, if the server reported one.
+    pub score: Option<f64>,
+    /// The response body parsed as JSON, if it was JSON.
+    pub details: Option<serde_json::Value>,
+    /// The response body as received.
+    pub raw: String,
+}
+
+impl SolveResult {
+    /// Interprets a solve response body. Never fails: a body that isn't JSON,
+    /// or has no numeric `score`, is kept as raw text.
+    #[cfg(any(feature = "http", test))]
+    pub fn parse(raw: String) -> Self {
+        let details = serde_json::from_str::<serde_json::Value>(&raw).ok();
+        let score = details
+            .as_ref()
+            .and_then(|value| value.get("score"))
+            .and_then(serde_json::Value::as_f64);
+        Self {
+            score,
+            details,
+            raw,
+        }
+    }
+}
+
+impl fmt::Display for SolveResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.raw.trim_end())
+    }
+}
+
+/// A test problem as handed out by the challenge server.
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub orders: Vec<Order>,
+    pub test_id: String,
+    /// The seed sent to the server; the random one drawn for a requested seed of 0.
+    pub seed: u64,
+}
+
+/// The challenge server operations the harness needs, so runs can be driven
+/// by something other than the real HTTP client.
+pub trait ChallengeClient {
+    /// Fetches a new problem. A `seed` of 0 asks for a random one.
+    fn challenge(&mut self, name: &str, seed: u64) -> Result<Problem>;
+
+    /// Submits the actions for a problem and returns the server's verdict.
+    fn solve(
+        &mut self,
+        test_id: &str,
+        rate: Duration,
+        min: Duration,
+        max: Duration,
+        actions: &[Action],
+    ) -> Result<SolveResult>;
+}
+
+/// JSON body [`ChallengeClient::solve`] submits for the given options and actions.
+pub fn solve_body(
+    rate: Duration,
+    min: Duration,
+    max: Duration,
+    actions: &[Action],
+) -> serde_json::Value {
+    json!({
+        "options": {
+            "rate": rate.as_micros(),
+            "min": min.as_micros(),
+            "max": max.as_micros(),
+        },
+        "actions": actions
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn units_round_trip_through_their_names() {
+        for location in Location::ALL {
+            for index in 0..3 {
+                let unit = Unit { location, index };
+                assert_eq!(unit.to_string().parse::<Unit>().unwrap(), unit);
+            }
+        }
+        assert_eq!(
+            Unit {
+                location: Location::Heater,
+                index: 1
+            }
+            .to_string(),
+            "heater-2"
+        );
+        for name in ["heater-1", "heater-0", "heater-x", "oven", "oven-2", ""] {
+            assert!(name.parse::<Unit>().is_err(), "{name:?}");
+        }
+    }
+
+    #[test]
+    fn actions_keep_their_unit_on_the_wire() {
+        let action = Action {
+            unit: 2,
+            ..Action::new("a", ActionKind::Move, Location::Cooler, UNIX_EPOCH)
+        };
+        let json = serde_json::to_string(&action).unwrap();
+        assert!(json.contains(r#""target":"cooler-3""#), "{json}");
+        let read: Action = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            (read.id, read.action, read.target, read.unit),
+            ("a".to_string(), ActionKind::Move, Location::Cooler, 2)
+        );
+    }
+
+    #[test]
+    fn deadlines_and_prices_are_optional() {
+        let order: Order = serde_json::from_str(
+            r#"{"id": "a", "name": "Soup", "temp": "hot", "price": 12, "freshness": 60, "deadline_secs": 30}"#,
+        )
+        .unwrap();
+        assert_eq!((order.price, order.deadline_secs), (12, Some(30)));
+
+        let order: Order = serde_json::from_str(
+            r#"{"id": "b", "name": "Salad", "temp": "cold", "freshness": 90}"#,
+        )
+        .unwrap();
+        assert_eq!((order.price, order.deadline_secs), (0, None));
+        assert_eq!(order.temp, Temp::Cold);
+    }
+}
@@ -0,0 +1,550 @@
+use crate::info;
+use crate::kitchen::{Kitchen, KitchenConfig, PickupOutcome};
+use crate::model::Location;
+use crate::model::Order;
+
+use rand::Rng;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+/// Peak number of orders in the kitchen at once for a schedule, against its total capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeakEstimate {
+    /// Peak if every order is picked up halfway through the pickup window.
+    pub expected: usize,
+    /// Peak if every order is picked up at the end of the window.
+    pub worst_case: usize,
+    /// Cooler, heater and shelf slots combined, over all their units.
+    pub capacity: usize,
+}
+
+impl PeakEstimate {
+    /// False if even the expected peak can't fit, so discards are guaranteed.
+    pub fn is_feasible(&self) -> bool {
+        self.expected <= self.capacity
+    }
+}
+
+/// Estimates how many orders will be stored at once when `count` orders are
+/// placed every `rate` and picked up between `min` and `max` after placement
+/// into a kitchen built from `config`. Ignores temperatures and discards; it
+/// only says whether everything could fit.
+pub fn estimate_peak_occupancy(
+    count: usize,
+    rate: Duration,
+    min: Duration,
+    max: Duration,
+    config: &KitchenConfig,
+) -> PeakEstimate {
+    PeakEstimate {
+        expected: peak_occupancy(count, rate, (min + max) / 2),
+        worst_case: peak_occupancy(count, rate, max),
+        capacity: [Location::Cooler, Location::Heater, Location::Shelf]
+            .map(|location| config.capacity(location))
+            .iter()
+            .sum(),
+    }
+}
+
+// most of `count` orders, placed `rate` apart and each stored for `stay`, present at once
+fn peak_occupancy(count: usize, rate: Duration, stay: Duration) -> usize {
+    if rate.is_zero() {
+        return count;
+    }
+    // every order stays equally long, so order i is still there when order i + k arrives
+    // as long as k × rate < stay; a pickup at the same instant as a placement frees its slot first
+    let overlapping = stay.as_nanos().div_ceil(rate.as_nanos()).max(1);
+    count.min(overlapping.try_into().unwrap_or(usize::MAX))
+}
+
+// longest a sleeping thread goes without checking the stop flag
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long after placement each order is picked up. Every sample is clamped
+/// into `[min, max]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PickupDistribution {
+    /// Whole seconds drawn uniformly from `[min, max]`.
+    Uniform { min: Duration, max: Duration },
+    /// `min` plus an exponentially distributed delay with the given mean, so most
+    /// pickups cluster near `min` with a long tail.
+    Exponential {
+        min: Duration,
+        max: Duration,
+        mean: Duration,
+    },
+    /// Normally distributed around `mean`.
+    Normal {
+        min: Duration,
+        max: Duration,
+        mean: Duration,
+        std_dev: Duration,
+    },
+}
+
+impl PickupDistribution {
+    pub fn sample(&self, rng: &mut impl Rng) -> Duration {
+        let (min, max, secs) = match *self {
+            PickupDistribution::Uniform { min, max } => {
+                let secs = rng.random_range(min.as_secs()..=max.as_secs().max(min.as_secs()));
+                (min, max, secs as f64)
+            }
+            PickupDistribution::Exponential { min, max, mean } => {
+                // inverse transform; 1 - u is in (0, 1] so the log is finite
+                let u: f64 = rng.random();
+                let secs = min.as_secs_f64() - mean.as_secs_f64() * (1.0 - u).ln();
+                (min, max, secs)
+            }
+            PickupDistribution::Normal {
+                min,
+                max,
+                mean,
+                std_dev,
+            } => {
+                // Box-Muller
+                let u1: f64 = 1.0 - rng.random::<f64>();
+                let u2: f64 = rng.random();
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                (min, max, mean.as_secs_f64() + std_dev.as_secs_f64() * z)
+            }
+        };
+        Duration::from_secs_f64(
+            secs.clamp(min.as_secs_f64(), max.as_secs_f64().max(min.as_secs_f64())),
+        )
+    }
+}
+
+/// What happens to an order at its scheduled time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Pickup,
+    /// The customer cancelled the order, so it is discarded instead.
+    Cancel,
+}
+
+/// Sleeps the current thread until `deadline`, returning immediately if it has passed.
+/// Wakes early and returns false once `stop` is set.
+pub fn sleep_until(deadline: SystemTime, stop: &AtomicBool) -> bool {
+    while let Ok(wait) = deadline.duration_since(SystemTime::now()) {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        thread::sleep(wait.min(STOP_POLL_INTERVAL));
+    }
+    !stop.load(Ordering::Relaxed)
+}
+
+// Handle to the worker threads from spawn_workers.
+struct Workers {
+    handles: Vec<JoinHandle<()>>,
+    outstanding: Arc<AtomicUsize>,
+}
+
+impl Workers {
+    // Waits for every worker to exit and returns how many events were left
+    // undone. Once this returns, every action the workers recorded has reached
+    // the kitchen's ledger.
+    fn join(self) -> usize {
+        for handle in self.handles {
+            handle.join().unwrap();
+        }
+        self.outstanding.load(Ordering::Acquire)
+    }
+}
+
+// performs a scheduled pickup or cancellation at `now`
+fn fire(kitchen: &Kitchen, order_id: &str, event: Event, now: SystemTime) {
+    match event {
+        Event::Pickup => match kitchen.pickup_order(order_id, now) {
+            // e.g. rejected by the overflow policy, or missing from the placements
+            PickupOutcome::Unknown => {
+                info!("nothing to pick up for order {order_id}, it was never stored")
+            }
+            // the kitchen records these, and reports repeated pickups itself
+            PickupOutcome::PickedUp
+            | PickupOutcome::Expired
+            | PickupOutcome::Late
+            | PickupOutcome::Discarded
+            | PickupOutcome::AlreadyPickedUp
+            | PickupOutcome::AlreadyDiscarded => {}
+        },
+        Event::Cancel => kitchen.cancel_order(order_id, now),
+    }
+    #[cfg(debug_assertions)]
+    if let Err(errors) = kitchen.check_invariants() {
+        let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+        panic!(
+            "kitchen invariants broken after {event:?} of {order_id}: {}",
+            errors.join("; ")
+        );
+    }
+}
+
+/// Runs the whole schedule on the calling thread against a virtual clock
+/// instead of the wall clock: every placement and pickup happens at exactly its
+/// scheduled time, in time order, with no sleeping in between. At equal times
+/// placements go first, so an order is always placed before it is picked up.
+///
+/// Returns how many pickups were left undone, which is only ever nonzero once
+/// `stop` is set. Nothing else runs while a placement does, so the kitchen must
+/// not use `OverflowPolicy::WaitForSlot`, which would wait forever.
+pub fn run_virtual(
+    kitchen: &Kitchen,
+    placements: Vec<(SystemTime, Order)>,
+    mut pickups: Vec<(SystemTime, String, Event)>,
+    stop: &AtomicBool,
+) -> usize {
+    pickups.sort_by_key(|(due, _, _)| *due);
+    let mut pickups = pickups.into_iter().peekable();
+
+    for (placement_time, order) in placements {
+        while let Some((due, order_id, event)) =
+            pickups.next_if(|(due, _, _)| *due < placement_time)
+        {
+            if stop.load(Ordering::Relaxed) {
+                return pickups.len() + 1;
+            }
+            fire(kitchen, &order_id, event, due);
+        }
+        if stop.load(Ordering::Relaxed) {
+            return pickups.len();
+        }
+        kitchen.place_order_scheduled(order, Some(placement_time), placement_time);
+    }
+
+    while let Some((due, order_id, event)) = pickups.next() {
+        if stop.load(Ordering::Relaxed) {
+            return pickups.len() + 1;
+        }
+        fire(kitchen, &order_id, event, due);
+    }
+    0
+}
+
+/// Runs the schedule against the wall clock: a placement thread sleeps until
+/// each placement is due while `workers` threads do the same for the pickups.
+/// A pickup never fires before its order has been placed, even when it is due
+/// first, as with a zero pickup delay or a placement running late; it then
+/// fires as soon as the placement is done.
+///
+/// Returns how many pickups were left undone, which is only ever nonzero once
+/// `stop` is set. Once this returns, every action recorded on the spawned
+/// threads has reached the kitchen's ledger.
+pub fn run_wall_clock(
+    kitchen: &Arc<Kitchen>,
+    placements: Vec<(SystemTime, Order)>,
+    pickups: Vec<(SystemTime, String, Event)>,
+    workers: usize,
+    stop: &Arc<AtomicBool>,
+) -> usize {
+    let mut index = HashMap::with_capacity(placements.len());
+    for (idx, (_, order)) in placements.iter().enumerate() {
+        index.entry(order.id.clone()).or_insert(idx);
+    }
+    let progress = Arc::new(PlacementProgress::default());
+
+    let placement_kitchen = kitchen.clone();
+    let placement_progress = progress.clone();
+    let placement_stop = stop.clone();
+    let placement_handle = thread::spawn(move || {
+        for (placement_time, order) in placements {
+            if !sleep_until(placement_time, &placement_stop) {
+                break;
+            }
+            placement_kitchen.place_order_scheduled(order, Some(placement_time), SystemTime::now());
+            placement_progress.advance();
+        }
+    });
+
+    let pickups = pickups
+        .into_iter()
+        .map(|(due, order_id, event)| {
+            // an order that is never placed has nothing to wait for
+            let placement = index.get(&order_id).copied();
+            (due, (order_id, event, placement))
+        })
+        .collect();
+    let kitchen = kitchen.clone();
+    let worker_stop = stop.clone();
+    let pickup_workers = spawn_workers(
+        pickups,
+        workers,
+        stop.clone(),
+        move |_, (order_id, event, placement)| {
+            if let Some(placement) = placement
+                && !progress.wait_for(placement, &worker_stop)
+            {
+                return false;
+            }
+            fire(&kitchen, &order_id, event, SystemTime::now());
+            true
+        },
+    );
+
+    placement_handle.join().unwrap();
+    pickup_workers.join()
+}
+
+// How many placements the placement thread has finished, for pickups to wait on.
+#[derive(Default)]
+struct PlacementProgress {
+    placed: Mutex<usize>,
+    advanced: Condvar,
+}
+
+impl PlacementProgress {
+    fn advance(&self) {
+        *self.placed.lock().unwrap() += 1;
+        self.advanced.notify_all();
+    }
+
+    // Blocks until placement `idx` is done. Returns false instead once `stop` is
+    // set, which also covers the placement thread giving up before reaching it.
+    fn wait_for(&self, idx: usize, stop: &AtomicBool) -> bool {
+        let mut placed = self.placed.lock().unwrap();
+        while *placed <= idx {
+            if stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            placed = self
+                .advanced
+                .wait_timeout(placed, STOP_POLL_INTERVAL)
+                .unwrap()
+                .0;
+        }
+        true
+    }
+}
+
+// Runs `handle` with each event's due time and payload once it's due, on
+// `workers` threads taking events earliest-first, so every worker is either
+// idle or waiting on an event due no later than any still queued; as long as
+// `handle` is quick, each event fires on schedule just as it would with a
+// dedicated thread per event. A worker exits, leaving the event outstanding,
+// when `handle` returns false or once `stop` is set.
+fn spawn_workers<T: Send + 'static>(
+    mut events: Vec<(SystemTime, T)>,
+    workers: usize,
+    stop: Arc<AtomicBool>,
+    handle: impl Fn(SystemTime, T) -> bool + Send + Sync + 'static,
+) -> Workers {
+    events.sort_by_key(|(due, _)| *due);
+    let outstanding = Arc::new(AtomicUsize::new(events.len()));
+    let queue = Arc::new(Mutex::new(VecDeque::from(events)));
+    let handle = Arc::new(handle);
+
+    let handles = (0..workers)
+        .map(|_| {
+            let queue = queue.clone();
+            let stop = stop.clone();
+            let outstanding = outstanding.clone();
+            let handle = handle.clone();
+            thread::spawn(move || {
+                loop {
+                    // release the queue before sleeping so other workers can take the next event
+                    let next = queue.lock().unwrap().pop_front();
+                    let Some((due, event)) = next else {
+                        break;
+                    };
+                    if !sleep_until(due, &stop) {
+                        break;
+                    }
+                    if !handle(due, event) {
+                        break;
+                    }
+                    outstanding.fetch_sub(1, Ordering::Release);
+                }
+            })
+        })
+        .collect();
+
+    Workers {
+        handles,
+        outstanding,
+    }
+}
+
+/// How late scheduled events woke up, from [`calibrate`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DriftStats {
+    pub count: usize,
+    pub mean: Duration,
+    pub p50: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl fmt::Display for DriftStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} events, woke late by mean {:?} / p50 {:?} / p99 {:?} / max {:?}",
+            self.count, self.mean, self.p50, self.p99, self.max
+        )
+    }
+}
+
+/// Measures how accurately this machine hits scheduled times: schedules
+/// `events` no-ops `rate` apart on `workers` pickup worker threads, the same
+/// way pickups are scheduled, and records how late each one woke up. Needs no
+/// kitchen or server. Stops early once `stop` is set, counting only the events
+/// that ran.
+pub fn calibrate(
+    events: usize,
+    rate: Duration,
+    workers: usize,
+    stop: Arc<AtomicBool>,
+) -> DriftStats {
+    // leave the workers time to start before the first event is due
+    let start = SystemTime::now() + rate.max(Duration::from_millis(10));
+    let schedule = (0..events as u32).map(|i| (start + rate * i, ())).collect();
+    let drifts = Arc::new(Mutex::new(Vec::with_capacity(events)));
+
+    let recorder = drifts.clone();
+    spawn_workers(schedule, workers, stop, move |due, ()| {
+        let drift = SystemTime::now().duration_since(due).unwrap_or_default();
+        recorder.lock().unwrap().push(drift);
+        true
+    })
+    .join();
+
+    let mut drifts = std::mem::take(&mut *drifts.lock().unwrap());
+    if drifts.is_empty() {
+        return DriftStats::default();
+    }
+    drifts.sort_unstable();
+    // nearest rank
+    let rank = |percent: usize| drifts[(drifts.len() * percent).div_ceil(100).max(1) - 1];
+    DriftStats {
+        count: drifts.len(),
+        mean: drifts.iter().sum::<Duration>() / drifts.len() as u32,
+        p50: rank(50),
+        p99: rank(99),
+        max: drifts[drifts.len() - 1],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Temp;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn peaks_count_the_orders_a_stay_overlaps() {
+        let rate = Duration::from_millis(500);
+        // a pickup at the same instant as the next placements frees its slot first
+        assert_eq!(peak_occupancy(100, rate, rate * 4), 4);
+        assert_eq!(
+            peak_occupancy(100, rate, rate * 4 + Duration::from_nanos(1)),
+            5
+        );
+        // one order at a time, however short its stay
+        assert_eq!(peak_occupancy(100, rate, Duration::ZERO), 1);
+        assert_eq!(peak_occupancy(3, rate, rate * 10), 3);
+        // a burst is all there at once
+        assert_eq!(peak_occupancy(100, Duration::ZERO, Duration::ZERO), 100);
+    }
+
+    #[test]
+    fn estimates_put_expected_and_late_pickups_against_capacity() {
+        let config = KitchenConfig::default();
+        let capacity = [Location::Cooler, Location::Heater, Location::Shelf]
+            .map(|location| config.capacity(location))
+            .iter()
+            .sum();
+        let (min, max) = (Duration::from_secs(4), Duration::from_secs(8));
+
+        let estimate = estimate_peak_occupancy(100, Duration::from_millis(500), min, max, &config);
+        assert_eq!(
+            estimate,
+            PeakEstimate {
+                expected: 12,
+                worst_case: 16,
+                capacity,
+            }
+        );
+        assert!(estimate.is_feasible());
+
+        let estimate = estimate_peak_occupancy(100, Duration::from_millis(100), min, max, &config);
+        assert_eq!((estimate.expected, estimate.worst_case), (60, 80));
+        assert!(!estimate.is_feasible());
+    }
+
+    #[test]
+    fn samples_stay_within_the_window() {
+        let (min, max) = (Duration::from_secs(2), Duration::from_secs(4));
+        let distributions = [
+            PickupDistribution::Uniform { min, max },
+            // a mean far past the window, so most draws need clamping
+            PickupDistribution::Exponential {
+                min,
+                max,
+                mean: Duration::from_secs(60),
+            },
+            PickupDistribution::Normal {
+                min,
+                max,
+                mean: Duration::from_secs(3),
+                std_dev: Duration::from_secs(10),
+            },
+        ];
+        let mut rng = StdRng::seed_from_u64(573);
+        for distribution in distributions {
+            for _ in 0..1_000 {
+                let sample = distribution.sample(&mut rng);
+                assert!(
+                    min <= sample && sample <= max,
+                    "{distribution:?}: {sample:?}"
+                );
+            }
+        }
+
+        // an inverted window collapses onto min instead of panicking
+        let inverted = PickupDistribution::Uniform { min: max, max: min };
+        assert_eq!(inverted.sample(&mut rng), max);
+    }
+
+    #[test]
+    fn pickups_of_orders_never_placed_are_skipped() {
+        let kitchen = Kitchen::with_config(KitchenConfig::default());
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let placed = Order {
+            id: "placed".to_string(),
+            name: "placed".to_string(),
+            temp: Temp::Room,
+            price: 10,
+            freshness: 60,
+            deadline_secs: None,
+        };
+        let pickups = vec![
+            (
+                start + Duration::from_secs(1),
+                "ghost".to_string(),
+                Event::Pickup,
+            ),
+            (
+                start + Duration::from_secs(2),
+                "placed".to_string(),
+                Event::Pickup,
+            ),
+        ];
+        let unfinished = run_virtual(
+            &kitchen,
+            vec![(start, placed)],
+            pickups,
+            &AtomicBool::new(false),
+        );
+        assert_eq!(unfinished, 0);
+
+        let actions = kitchen.get_actions();
+        assert!(actions.iter().all(|action| action.id == "placed"));
+        assert_eq!(actions.len(), 2);
+    }
+}
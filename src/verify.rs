@@ -0,0 +1,363 @@
+//! Offline checks over a recorded action ledger, run before submitting it.
+
+use crate::kitchen::KitchenConfig;
+use crate::model::{Action, ActionKind, Order, Unit};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A single invariant violation, identified by its index in the ledger.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionError {
+    UnknownOrder {
+        index: usize,
+        id: String,
+    },
+    NonIncreasingTimestamp {
+        index: usize,
+        timestamp: u64,
+        previous: u64,
+    },
+    NotPlaced {
+        index: usize,
+        id: String,
+        action: ActionKind,
+    },
+    AlreadyPlaced {
+        index: usize,
+        id: String,
+    },
+    WrongLocation {
+        index: usize,
+        id: String,
+        target: Unit,
+        actual: Unit,
+    },
+    OverCapacity {
+        index: usize,
+        target: Unit,
+        capacity: usize,
+    },
+    UnknownUnit {
+        index: usize,
+        target: Unit,
+        units: usize,
+    },
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionError::UnknownOrder { index, id } => {
+                write!(f, "#{index}: order {id} is not part of the problem")
+            }
+            ActionError::NonIncreasingTimestamp {
+                index,
+                timestamp,
+                previous,
+            } => write!(
+                f,
+                "#{index}: timestamp {timestamp} does not follow previous {previous}"
+            ),
+            ActionError::NotPlaced { index, id, action } => {
+                write!(
+                    f,
+                    "#{index}: {action} of order {id} which is not in the kitchen"
+                )
+            }
+            ActionError::AlreadyPlaced { index, id } => {
+                write!(f, "#{index}: order {id} placed more than once")
+            }
+            ActionError::WrongLocation {
+                index,
+                id,
+                target,
+                actual,
+            } => write!(
+                f,
+                "#{index}: order {id} is on the {actual}, not the {target}"
+            ),
+            ActionError::OverCapacity {
+                index,
+                target,
+                capacity,
+            } => write!(f, "#{index}: {target} exceeds its capacity of {capacity}"),
+            ActionError::UnknownUnit {
+                index,
+                target,
+                units,
+            } => write!(
+                f,
+                "#{index}: {target} does not exist, the kitchen has {units} {} unit(s)",
+                target.location
+            ),
+        }
+    }
+}
+
+/// Replays `actions` in ledger order and reports every broken invariant: orders
+/// are placed once before anything else happens to them, moves, pickups and
+/// discards happen where the order actually is, every unit exists and none
+/// exceeds its capacity, timestamps strictly increase, and every order is in
+/// the problem. Unit counts and capacities come from `config`, the kitchen that
+/// recorded the actions.
+pub fn validate_actions(
+    actions: &[Action],
+    orders: &[Order],
+    config: &KitchenConfig,
+) -> Result<(), Vec<ActionError>> {
+    let known: HashSet<&str> = orders.iter().map(|order| order.id.as_str()).collect();
+
+    let mut errors = Vec::new();
+    let mut placed = HashSet::new();
+    let mut location: HashMap<&str, Unit> = HashMap::new();
+    let mut occupancy: HashMap<Unit, usize> = HashMap::new();
+    let mut previous: Option<u64> = None;
+
+    for (index, action) in actions.iter().enumerate() {
+        let id = action.id.as_str();
+        let target = action.target_unit();
+
+        if let Some(previous) = previous
+            && action.timestamp <= previous
+        {
+            errors.push(ActionError::NonIncreasingTimestamp {
+                index,
+                timestamp: action.timestamp,
+                previous,
+            });
+        }
+        previous = Some(action.timestamp);
+
+        if !known.contains(id) {
+            errors.push(ActionError::UnknownOrder {
+                index,
+                id: id.to_string(),
+            });
+        }
+
+        let units = config.units(target.location);
+        if target.index >= units {
+            errors.push(ActionError::UnknownUnit {
+                index,
+                target,
+                units,
+            });
+        }
+
+        match action.action {
+            ActionKind::Place => {
+                if !placed.insert(id) {
+                    errors.push(ActionError::AlreadyPlaced {
+                        index,
+                        id: id.to_string(),
+                    });
+                    continue;
+                }
+                location.insert(id, target);
+                *occupancy.entry(target).or_default() += 1;
+            }
+            ActionKind::Move | ActionKind::Pickup | ActionKind::Discard => {
+                let Some(&actual) = location.get(id) else {
+                    errors.push(ActionError::NotPlaced {
+                        index,
+                        id: id.to_string(),
+                        action: action.action,
+                    });
+                    continue;
+                };
+
+                if action.action == ActionKind::Move {
+                    // the target of a move is where the order ends up
+                    *occupancy.entry(actual).or_default() -= 1;
+                    location.insert(id, target);
+                    *occupancy.entry(target).or_default() += 1;
+                } else {
+                    if actual != target {
+                        errors.push(ActionError::WrongLocation {
+                            index,
+                            id: id.to_string(),
+                            target,
+                            actual,
+                        });
+                    }
+                    location.remove(id);
+                    *occupancy.entry(actual).or_default() -= 1;
+                    continue;
+                }
+            }
+        }
+
+        let capacity = config.unit_capacity(target.location);
+        if occupancy[&target] > capacity {
+            errors.push(ActionError::OverCapacity {
+                index,
+                target,
+                capacity,
+            });
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kitchen::Kitchen;
+    use crate::model::{Location, Temp};
+
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn order(id: &str) -> Order {
+        Order {
+            id: id.to_string(),
+            name: id.to_string(),
+            temp: Temp::Hot,
+            price: 10,
+            freshness: 60,
+            deadline_secs: None,
+        }
+    }
+
+    fn action(
+        timestamp: u64,
+        id: &str,
+        action: ActionKind,
+        target: Location,
+        unit: usize,
+    ) -> Action {
+        Action {
+            timestamp,
+            id: id.to_string(),
+            action,
+            target,
+            unit,
+            seq: 0,
+            reason: None,
+            group: 0,
+        }
+    }
+
+    fn unit(location: Location, index: usize) -> Unit {
+        Unit { location, index }
+    }
+
+    #[test]
+    fn a_consistent_ledger_passes() {
+        let orders = [order("a"), order("b")];
+        let actions = [
+            action(1, "a", ActionKind::Place, Location::Shelf, 0),
+            action(2, "b", ActionKind::Place, Location::Heater, 0),
+            action(3, "a", ActionKind::Move, Location::Heater, 0),
+            action(4, "a", ActionKind::Pickup, Location::Heater, 0),
+            action(5, "b", ActionKind::Discard, Location::Heater, 0),
+        ];
+        assert_eq!(
+            validate_actions(&actions, &orders, &KitchenConfig::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn each_broken_invariant_is_reported_where_it_happens() {
+        let orders = [order("a"), order("b")];
+        let actions = [
+            action(10, "a", ActionKind::Place, Location::Shelf, 0),
+            action(10, "b", ActionKind::Place, Location::Cooler, 0),
+            action(11, "ghost", ActionKind::Place, Location::Shelf, 0),
+            action(12, "a", ActionKind::Place, Location::Shelf, 0),
+            action(13, "b", ActionKind::Pickup, Location::Shelf, 0),
+            action(14, "b", ActionKind::Pickup, Location::Cooler, 0),
+        ];
+        let errors = validate_actions(&actions, &orders, &KitchenConfig::default()).unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                ActionError::NonIncreasingTimestamp {
+                    index: 1,
+                    timestamp: 10,
+                    previous: 10,
+                },
+                ActionError::UnknownOrder {
+                    index: 2,
+                    id: "ghost".to_string(),
+                },
+                ActionError::AlreadyPlaced {
+                    index: 3,
+                    id: "a".to_string(),
+                },
+                ActionError::WrongLocation {
+                    index: 4,
+                    id: "b".to_string(),
+                    target: unit(Location::Shelf, 0),
+                    actual: unit(Location::Cooler, 0),
+                },
+                ActionError::NotPlaced {
+                    index: 5,
+                    id: "b".to_string(),
+                    action: ActionKind::Pickup,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn overfilling_a_unit_is_reported() {
+        let config = KitchenConfig::default();
+        let capacity = config.unit_capacity(Location::Heater);
+        let orders: Vec<Order> = (0..=capacity).map(|idx| order(&idx.to_string())).collect();
+        let actions: Vec<Action> = orders
+            .iter()
+            .enumerate()
+            .map(|(idx, order)| {
+                action(
+                    idx as u64,
+                    &order.id,
+                    ActionKind::Place,
+                    Location::Heater,
+                    0,
+                )
+            })
+            .collect();
+        assert_eq!(
+            validate_actions(&actions, &orders, &config),
+            Err(vec![ActionError::OverCapacity {
+                index: capacity,
+                target: unit(Location::Heater, 0),
+                capacity,
+            }])
+        );
+    }
+
+    #[test]
+    fn units_the_kitchen_lacks_are_rejected_not_replayed() {
+        let config = KitchenConfig::default();
+        assert_eq!(config.units(Location::Heater), 1);
+        let orders = [order("a")];
+        let actions = [action(1, "a", ActionKind::Place, Location::Heater, 1)];
+
+        let errors = validate_actions(&actions, &orders, &config).unwrap_err();
+        assert_eq!(
+            errors,
+            [ActionError::UnknownUnit {
+                index: 0,
+                target: unit(Location::Heater, 1),
+                units: 1,
+            }]
+        );
+        assert_eq!(
+            errors[0].to_string(),
+            "#0: heater-2 does not exist, the kitchen has 1 heater unit(s)"
+        );
+
+        // replaying it would otherwise index past the kitchen's units
+        let up_to = UNIX_EPOCH + Duration::from_secs(1);
+        let replayed = Kitchen::from_actions(config, &orders, &actions, up_to);
+        assert_eq!(replayed.err(), Some(errors));
+    }
+}